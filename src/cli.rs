@@ -0,0 +1,933 @@
+use crate::{
+    config::{self, Kind, Scope},
+    image::TagVariants,
+    log::Level,
+    misc::suggest,
+    runtime::{MountMode, ProjectRoot, TtyMode},
+    Error,
+};
+use std::fmt::Write;
+
+/// A flag which may be accepted by a subcommand.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flag {
+    DockerCmd,
+    DryRun,
+    Effective,
+    Env,
+    Force,
+    Gpu,
+    Help,
+    Here,
+    Interactive,
+    Jupyter,
+    Name,
+    NoTty,
+    Port,
+    Project,
+    Python3,
+    Quiet,
+    Root,
+    SamePath,
+    User,
+    Verbose,
+}
+
+struct FlagSpec {
+    flag:  Flag,
+    long:  &'static str,
+    short: Option<char>,
+    value: Option<&'static str>,
+    about: &'static str,
+}
+
+const FLAGS: &[FlagSpec] = &[
+    FlagSpec {
+        flag:  Flag::DryRun,
+        long:  "dry-run",
+        short: None,
+        value: None,
+        about: "Print the commands that would be run, without running them",
+    },
+    FlagSpec {
+        flag:  Flag::Effective,
+        long:  "effective",
+        short: None,
+        value: None,
+        about: "List the merged configuration, and where each value was defined",
+    },
+    FlagSpec {
+        flag:  Flag::Env,
+        long:  "env",
+        short: Some('e'),
+        value: Some("KEY=VALUE"),
+        about: "Sets an environment variable in the container, or passes KEY through from the host",
+    },
+    FlagSpec {
+        flag:  Flag::Force,
+        long:  "force",
+        short: Some('f'),
+        value: None,
+        about: "Apply the subcommand by force (ie: force removal)",
+    },
+    FlagSpec {
+        flag:  Flag::Gpu,
+        long:  "gpu",
+        short: None,
+        value: None,
+        about: "Uses an image which supports GPU compute",
+    },
+    FlagSpec {
+        flag:  Flag::DockerCmd,
+        long:  "docker-cmd",
+        short: None,
+        value: Some("COMMAND"),
+        about: "Call COMMAND when invoking docker",
+    },
+    FlagSpec {
+        flag:  Flag::Here,
+        long:  "here",
+        short: None,
+        value: None,
+        about: "Mount the current directory instead of the root of the project",
+    },
+    FlagSpec {
+        flag:  Flag::Interactive,
+        long:  "interactive",
+        short: None,
+        value: None,
+        about: "Always allocate a terminal for the container, even if stdin or stdout is not one",
+    },
+    FlagSpec {
+        flag:  Flag::Jupyter,
+        long:  "jupyter",
+        short: None,
+        value: None,
+        about: "Uses an image which has Jupyter preinstalled",
+    },
+    FlagSpec {
+        flag:  Flag::Name,
+        long:  "name",
+        short: None,
+        value: Some("NAME"),
+        about: "Gives NAME to the container when it is launched",
+    },
+    FlagSpec {
+        flag:  Flag::NoTty,
+        long:  "no-tty",
+        short: None,
+        value: None,
+        about: "Never allocate a terminal for the container; piped input is still forwarded",
+    },
+    FlagSpec {
+        flag:  Flag::Port,
+        long:  "port",
+        short: Some('p'),
+        value: Some("PORT"),
+        about: "Specifies a port mapping for the container and host",
+    },
+    FlagSpec {
+        flag:  Flag::Project,
+        long:  "project",
+        short: None,
+        value: None,
+        about: "Use the Tensorman.toml of the current project",
+    },
+    FlagSpec {
+        flag:  Flag::Python3,
+        long:  "python3",
+        short: None,
+        value: None,
+        about: "Uses an image which supports Python3",
+    },
+    FlagSpec {
+        flag:  Flag::Root,
+        long:  "root",
+        short: None,
+        value: None,
+        about: "Run the docker container as root",
+    },
+    FlagSpec {
+        flag:  Flag::SamePath,
+        long:  "same-path",
+        short: None,
+        value: None,
+        about: "Mount the project at its path on the host instead of /project",
+    },
+    FlagSpec {
+        flag:  Flag::User,
+        long:  "user",
+        short: None,
+        value: None,
+        about: "Use the user-wide configuration file",
+    },
+    FlagSpec {
+        flag:  Flag::Quiet,
+        long:  "quiet",
+        short: Some('q'),
+        value: None,
+        about: "Only print errors, and the output of docker",
+    },
+    FlagSpec {
+        flag:  Flag::Verbose,
+        long:  "verbose",
+        short: Some('v'),
+        value: None,
+        about: "Print each docker command before it is run, or debugging details with -vv",
+    },
+    FlagSpec {
+        flag:  Flag::Help,
+        long:  "help",
+        short: Some('h'),
+        value: None,
+        about: "Display this information",
+    },
+];
+
+const VARIANT_FLAGS: &[Flag] = &[Flag::Gpu, Flag::Jupyter, Flag::Python3];
+
+/// Flags which are accepted by every subcommand.
+const GLOBAL_FLAGS: &[Flag] = &[Flag::Help, Flag::Quiet, Flag::Verbose];
+
+/// A subcommand, along with the flags that it accepts.
+pub struct Subcommand {
+    pub name: &'static str,
+    usage:    &'static str,
+    about:    &'static str,
+    flags:    &'static [&'static [Flag]],
+}
+
+pub const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name:  "config",
+        usage: "config (get KEY | set KEY VALUE... | unset KEY | list | edit)",
+        about: "Reads and modifies configuration files.
+
+        Values are read from the merged configuration, unless --project or --user
+        selects a file. Changes are written to the user-wide configuration file,
+        unless --project is given. The edit action opens the file in $EDITOR, and
+        the file is only saved if it is still valid.",
+        flags: &[&[Flag::Effective, Flag::Project, Flag::User]],
+    },
+    Subcommand {
+        name:  "datasets",
+        usage: "datasets",
+        about: "Lists the datasets defined by [datasets] tables, whether their paths
+        exist, and whether the project uses them.",
+        flags: &[],
+    },
+    Subcommand {
+        name:  "default",
+        usage: "default TAG [VARIANTS...]",
+        about: "Defines the default tensorflow image to use when not specified",
+        flags: &[&[Flag::DryRun], VARIANT_FLAGS],
+    },
+    Subcommand {
+        name:  "fix-perms",
+        usage: "fix-perms [PATH]",
+        about: "Returns files created by root to the invoking user.
+
+        Files under PATH which are owned by root, such as checkpoints written
+        by a --root run, are changed to be owned by the invoking user. PATH
+        defaults to the directory that run would mount.",
+        flags: &[&[Flag::DockerCmd, Flag::DryRun], VARIANT_FLAGS],
+    },
+    Subcommand {
+        name:  "help",
+        usage: "help [SUBCOMMAND]",
+        about: "Display information about tensorman, or one of its subcommands",
+        flags: &[],
+    },
+    Subcommand {
+        name:  "home",
+        usage: "home (path | clean)",
+        about: "Shows or removes the home directory of the active image.
+
+        Each image has its own home directory, which persists caches and history
+        between runs. The path action prints where it is kept, and the clean
+        action removes it, along with everything in it.",
+        flags: &[VARIANT_FLAGS],
+    },
+    Subcommand {
+        name:  "list",
+        usage: "list",
+        about: "List tensorflow images installed on the system",
+        flags: &[&[Flag::DockerCmd]],
+    },
+    Subcommand {
+        name:  "print-command",
+        usage: "print-command SUBCOMMAND [FLAGS...] [ARGS...]",
+        about: "Prints the commands that a subcommand would run, without running them.
+
+        The commands are quoted for a shell, so that they may be pasted into
+        scripts. This is the same as giving --dry-run to the SUBCOMMAND, which
+        may be any of run, pull, remove, save, fix-perms, or default.",
+        flags: &[],
+    },
+    Subcommand {
+        name:  "pull",
+        usage: "pull [TAG]",
+        about: "Fetches and updates tensorflow images",
+        flags: &[&[Flag::DockerCmd, Flag::DryRun], VARIANT_FLAGS],
+    },
+    Subcommand {
+        name:  "remove",
+        usage: "remove ID...",
+        about: "Removes an image either by its sha sum, or tag",
+        flags: &[&[Flag::DockerCmd, Flag::DryRun, Flag::Force]],
+    },
+    Subcommand {
+        name:  "run",
+        usage: "run [FLAGS...] COMMAND [ARGS...]",
+        about: "Mounts an image and executes the given command.
+
+        Use a shell as the COMMAND interactive sessions. Flags for tensorman must
+        precede the COMMAND; every argument after it is passed to the COMMAND.",
+        flags: &[
+            &[
+                Flag::DockerCmd,
+                Flag::DryRun,
+                Flag::Env,
+                Flag::Here,
+                Flag::Interactive,
+                Flag::Name,
+                Flag::NoTty,
+                Flag::Port,
+                Flag::Root,
+                Flag::SamePath,
+            ],
+            VARIANT_FLAGS,
+        ],
+    },
+    Subcommand {
+        name:  "save",
+        usage: "save CONTAINER NAME",
+        about: "Saves an active container with the name of CONTAINER to a new image
+        which shall be named NAME.",
+        flags: &[&[Flag::DockerCmd, Flag::DryRun]],
+    },
+    Subcommand {
+        name:  "show",
+        usage: "show [IMAGE]",
+        about: "Show the active image that will be run, and where it was configured.
+
+        If an IMAGE is given, its details and the projects which pin it are shown.",
+        flags: &[&[Flag::DockerCmd], VARIANT_FLAGS],
+    },
+];
+
+impl Subcommand {
+    fn find(name: &str) -> Option<&'static Subcommand> {
+        SUBCOMMANDS.iter().find(|subcommand| subcommand.name == name)
+    }
+
+    fn accepts(&self, flag: Flag) -> bool {
+        GLOBAL_FLAGS.contains(&flag) || self.flags.iter().any(|flags| flags.contains(&flag))
+    }
+
+    fn flag_specs(&self) -> impl Iterator<Item = &'static FlagSpec> + '_ {
+        FLAGS.iter().filter(move |spec| self.accepts(spec.flag))
+    }
+}
+
+/// Flags given on the command line, which have been accepted by the subcommand.
+#[derive(Debug, Default)]
+pub struct Flags<'a> {
+    pub as_root:      bool,
+    pub docker_cmd:   Option<&'a str>,
+    pub dry_run:      bool,
+    pub effective:    bool,
+    pub env:          Vec<(&'a str, Option<&'a str>)>,
+    pub force:        bool,
+    pub name:         Option<&'a str>,
+    pub ports:        Vec<&'a str>,
+    pub project_root: Option<ProjectRoot>,
+    pub mount_mode:   Option<MountMode>,
+    pub scope:        Option<Scope>,
+    pub tty:          Option<TtyMode>,
+    pub variants:     TagVariants,
+    /// The verbosity selected by `-q` or `-v`, which overrides `TENSORMAN_LOG`.
+    pub verbosity:    Option<Level>,
+}
+
+/// An action of the `config` subcommand.
+#[derive(Debug)]
+pub enum ConfigAction<'a> {
+    Get(&'a str),
+    Set(&'a str, Vec<&'a str>),
+    Unset(&'a str),
+    List { effective: bool },
+    Edit,
+}
+
+const CONFIG_ACTIONS: &[&str] = &["get", "set", "unset", "list", "edit"];
+
+/// An action of the `home` subcommand.
+#[derive(Debug)]
+pub enum HomeAction {
+    Path,
+    Clean,
+}
+
+const HOME_ACTIONS: &[&str] = &["path", "clean"];
+
+/// The subcommand to execute, with its positional arguments.
+#[derive(Debug)]
+pub enum Command<'a> {
+    Config(ConfigAction<'a>),
+    Datasets,
+    Default { tag: &'a str, variants: Vec<&'a str> },
+    FixPerms { path: Option<&'a str> },
+    Help(Option<&'static str>),
+    Home(HomeAction),
+    List,
+    Pull { tag: Option<&'a str> },
+    Remove { images: Vec<&'a str> },
+    Run { cmd: &'a str, args: Vec<&'a str> },
+    Save { container: &'a str, image: &'a str },
+    Show { image: Option<&'a str> },
+    Version,
+}
+
+/// The parsed command-line arguments of tensorman.
+#[derive(Debug)]
+pub struct Cli<'a> {
+    pub tag:       Option<&'a str>,
+    pub container: Option<&'a str>,
+    pub command:   Command<'a>,
+    pub flags:     Flags<'a>,
+}
+
+impl<'a> Cli<'a> {
+    pub fn parse(arguments: &'a [String]) -> Result<Self, Error> {
+        let mut arguments = arguments.iter().map(String::as_str);
+        let mut cli = Cli {
+            tag:       None,
+            container: None,
+            command:   Command::Help(None),
+            flags:     <_>::default(),
+        };
+
+        // Allow the first argument, if it begins with `+` or `=`, to override the image.
+        let mut argument = arguments.next();
+        if let Some(first) = argument {
+            if let Some(tag) = first.strip_prefix('+') {
+                cli.tag = Some(tag);
+                argument = arguments.next();
+            } else if let Some(container) = first.strip_prefix('=') {
+                cli.container = Some(container);
+                argument = arguments.next();
+            }
+        }
+
+        // The verbosity may be given before the subcommand, as in `tensorman -v run`.
+        let mut verbose = 0;
+        let subcommand = loop {
+            match argument {
+                Some("-h") | Some("--help") => return Ok(cli),
+                Some("-V") | Some("--version") => {
+                    cli.command = Command::Version;
+                    return Ok(cli);
+                }
+                Some("-q") | Some("--quiet") => cli.flags.verbosity = Some(Level::Quiet),
+                Some("--verbose") => {
+                    verbose += 1;
+                    cli.flags.verbosity = Some(Level::verbose(verbose));
+                }
+                Some(flag) if flag.len() > 1 && flag.trim_end_matches('v') == "-" => {
+                    verbose += flag.len() - 1;
+                    cli.flags.verbosity = Some(Level::verbose(verbose));
+                }
+                Some(name) => {
+                    break Subcommand::find(name).ok_or_else(|| {
+                        let why = format!("unknown subcommand '{}'", name);
+                        usage(None, with_suggestion(why, name, subcommand_names()))
+                    })?
+                }
+                None => return Err(usage(None, "tensorman must be given a subcommand to execute")),
+            }
+
+            argument = arguments.next();
+        };
+
+        // Prints the commands of the subcommand which follows, as if it was given --dry-run.
+        let subcommand = match subcommand.name {
+            "print-command" => match arguments.next() {
+                Some("-h") | Some("--help") | None => {
+                    cli.command = Command::Help(Some(subcommand.name));
+                    return Ok(cli);
+                }
+                Some(name) => match Subcommand::find(name) {
+                    Some(inner) if inner.accepts(Flag::DryRun) => {
+                        cli.flags.dry_run = true;
+                        inner
+                    }
+                    _ => {
+                        let why = format!("cannot print the commands of '{}'", name);
+                        let names = SUBCOMMANDS
+                            .iter()
+                            .filter(|subcommand| subcommand.accepts(Flag::DryRun))
+                            .map(|subcommand| subcommand.name);
+                        return Err(usage(Some(subcommand), with_suggestion(why, name, names)));
+                    }
+                },
+            },
+            _ => subcommand,
+        };
+
+        let mut positionals = Vec::new();
+
+        while let Some(argument) = arguments.next() {
+            if argument == "--" {
+                positionals.extend(arguments.by_ref());
+                break;
+            }
+
+            if argument.len() < 2 || !argument.starts_with('-') {
+                positionals.push(argument);
+
+                // As with `docker run`, everything following the command belongs to it.
+                if subcommand.name == "run" {
+                    let mut remaining = arguments.by_ref().peekable();
+                    remaining.next_if(|&argument| argument == "--");
+                    positionals.extend(remaining);
+                    break;
+                }
+
+                continue;
+            }
+
+            let long = argument.starts_with("--");
+            let (flag, inline_value) = match argument.strip_prefix("--") {
+                Some(flag) => match flag.split_once('=') {
+                    Some((flag, value)) => (flag, Some(value)),
+                    None => (flag, None),
+                },
+                None => (&argument[1..], None),
+            };
+
+            // Repeated short flags are only meaningful for `-v`, as in `-vv`.
+            let (flag, count) = match flag.trim_start_matches('v') {
+                "" if !long => ("v", flag.len()),
+                _ => (flag, 1),
+            };
+
+            let spec = find_flag(subcommand.flag_specs(), flag, long)
+                .ok_or_else(|| unknown_flag(subcommand, flag, long))?;
+
+            let value = match (spec.value, inline_value) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(name), None) => Some(arguments.next().ok_or_else(|| {
+                    usage(
+                        Some(subcommand),
+                        format!("the --{} flag requires {} as an argument", spec.long, name),
+                    )
+                })?),
+                (None, Some(_)) => {
+                    return Err(usage(
+                        Some(subcommand),
+                        format!("the --{} flag does not take an argument", spec.long),
+                    ))
+                }
+                (None, None) => None,
+            };
+
+            let flags = &mut cli.flags;
+            match spec.flag {
+                Flag::DockerCmd => flags.docker_cmd = value,
+                Flag::DryRun => flags.dry_run = true,
+                Flag::Effective => flags.effective = true,
+                Flag::Env => {
+                    let value = value.unwrap_or_default();
+                    let (key, value) = match value.split_once('=') {
+                        Some((key, value)) => (key, Some(value)),
+                        None => (value, None),
+                    };
+
+                    if !config::is_variable_name(key) {
+                        return Err(usage(
+                            Some(subcommand),
+                            format!("invalid environment variable name '{}'", key),
+                        ));
+                    }
+
+                    flags.env.push((key, value));
+                }
+                Flag::Force => flags.force = true,
+                Flag::Gpu => flags.variants |= TagVariants::GPU,
+                Flag::Help => {
+                    cli.command = Command::Help(Some(subcommand.name));
+                    return Ok(cli);
+                }
+                Flag::Here => flags.project_root = Some(ProjectRoot::Here),
+                Flag::Interactive => flags.tty = Some(TtyMode::Always),
+                Flag::Jupyter => flags.variants |= TagVariants::JUPYTER,
+                Flag::Name => flags.name = value,
+                Flag::NoTty => flags.tty = Some(TtyMode::Never),
+                Flag::Port => flags.ports.extend(value),
+                Flag::Project => flags.scope = Some(Scope::Project),
+                Flag::Python3 => flags.variants |= TagVariants::PY3,
+                Flag::Quiet => flags.verbosity = Some(Level::Quiet),
+                Flag::Root => flags.as_root = true,
+                Flag::SamePath => flags.mount_mode = Some(MountMode::HostPath),
+                Flag::User => flags.scope = Some(Scope::User),
+                Flag::Verbose => {
+                    verbose += count;
+                    flags.verbosity = Some(Level::verbose(verbose));
+                }
+            }
+        }
+
+        cli.command = Command::new(subcommand, positionals, &cli.flags)?;
+        Ok(cli)
+    }
+}
+
+impl<'a> Command<'a> {
+    fn new(
+        subcommand: &'static Subcommand,
+        positionals: Vec<&'a str>,
+        flags: &Flags,
+    ) -> Result<Self, Error> {
+        let mut positionals = positionals.into_iter();
+        let missing = |what: &str| {
+            usage(Some(subcommand), format!("the {} subcommand requires {}", subcommand.name, what))
+        };
+
+        let command = match subcommand.name {
+            "config" => {
+                let action = positionals.next().ok_or_else(|| missing("an action"))?;
+                let mut key = || -> Result<&'a str, Error> {
+                    let key = positionals.next().ok_or_else(|| missing("a configuration key"))?;
+                    match config::key_kind(key) {
+                        Some(_) => Ok(key),
+                        None => {
+                            let why = format!("unknown configuration key '{}'", key);
+                            let keys = config::KEYS.iter().map(|&(name, _)| name);
+                            Err(usage(Some(subcommand), with_suggestion(why, key, keys)))
+                        }
+                    }
+                };
+
+                let action = match action {
+                    "get" => ConfigAction::Get(key()?),
+                    "set" => {
+                        let key = key()?;
+                        let values: Vec<&str> = positionals.by_ref().collect();
+                        if config::key_kind(key) != Some(Kind::List) && values.len() != 1 {
+                            return Err(missing(&format!("exactly one value for '{}'", key)));
+                        }
+
+                        ConfigAction::Set(key, values)
+                    }
+                    "unset" => ConfigAction::Unset(key()?),
+                    "list" => ConfigAction::List { effective: flags.effective },
+                    "edit" => ConfigAction::Edit,
+                    action => {
+                        let why = format!("unknown config action '{}'", action);
+                        let actions = CONFIG_ACTIONS.iter().copied();
+                        return Err(usage(Some(subcommand), with_suggestion(why, action, actions)));
+                    }
+                };
+
+                if flags.effective && !matches!(action, ConfigAction::List { .. }) {
+                    return Err(usage(Some(subcommand), "--effective may only be used with list"));
+                }
+
+                Command::Config(action)
+            }
+            "datasets" => Command::Datasets,
+            "default" => {
+                let tag = positionals.next().ok_or_else(|| missing("a tag"))?;
+                return Ok(Command::Default { tag, variants: positionals.collect() });
+            }
+            "fix-perms" => Command::FixPerms { path: positionals.next() },
+            "help" => Command::Help(match positionals.next() {
+                Some(name) => Some(
+                    Subcommand::find(name)
+                        .ok_or_else(|| {
+                            let why = format!("no help for unknown subcommand '{}'", name);
+                            usage(Some(subcommand), with_suggestion(why, name, subcommand_names()))
+                        })?
+                        .name,
+                ),
+                None => None,
+            }),
+            "home" => Command::Home(match positionals.next() {
+                Some("path") => HomeAction::Path,
+                Some("clean") => HomeAction::Clean,
+                Some(action) => {
+                    let why = format!("unknown home action '{}'", action);
+                    let actions = HOME_ACTIONS.iter().copied();
+                    return Err(usage(Some(subcommand), with_suggestion(why, action, actions)));
+                }
+                None => return Err(missing("an action")),
+            }),
+            "list" => Command::List,
+            "pull" => Command::Pull { tag: positionals.next() },
+            "remove" => {
+                let images: Vec<&str> = positionals.collect();
+                if images.is_empty() {
+                    return Err(missing("an image to remove"));
+                }
+
+                return Ok(Command::Remove { images });
+            }
+            "run" => {
+                let cmd = positionals.next().ok_or_else(|| missing("a command argument"))?;
+                return Ok(Command::Run { cmd, args: positionals.collect() });
+            }
+            "save" => Command::Save {
+                container: positionals
+                    .next()
+                    .ok_or_else(|| missing("a container name as a source"))?,
+                image:     positionals
+                    .next()
+                    .ok_or_else(|| missing("an image name as the destination"))?,
+            },
+            "show" => Command::Show { image: positionals.next() },
+            _ => unreachable!("subcommand '{}' is not handled", subcommand.name),
+        };
+
+        match positionals.next() {
+            Some(unexpected) => Err(usage(
+                Some(subcommand),
+                format!(
+                    "unexpected argument '{}' for the {} subcommand",
+                    unexpected, subcommand.name
+                ),
+            )),
+            None => Ok(command),
+        }
+    }
+}
+
+/// The usage line for a subcommand, or of tensorman as a whole.
+pub fn usage_line(subcommand: Option<&str>) -> String {
+    match subcommand.and_then(Subcommand::find) {
+        Some(subcommand) => format!("tensorman [+TAG | =CONTAINER] {}", subcommand.usage),
+        None => "tensorman [+TAG | =CONTAINER] [-q | -v] SUBCOMMAND [FLAGS...]".into(),
+    }
+}
+
+/// Generates the help text of a subcommand, or of tensorman as a whole.
+pub fn help(subcommand: Option<&str>) -> String {
+    let mut help = String::new();
+
+    match subcommand.and_then(Subcommand::find) {
+        Some(subcommand) => {
+            let _ = write!(
+                help,
+                "tensorman-{}\n    {}\n\nUSAGE:\n    {}\n\nFLAGS:",
+                subcommand.name,
+                subcommand.about.replace("\n        ", "\n    "),
+                usage_line(Some(subcommand.name))
+            );
+
+            for spec in subcommand.flag_specs() {
+                write_flag(&mut help, spec);
+            }
+        }
+        None => {
+            let _ = write!(
+                help,
+                "tensorman {}\n    Tensorflow Docker image manager\n\nUSAGE:\n    \
+                 {}\n\nSUBCOMMANDS:",
+                env!("CARGO_PKG_VERSION"),
+                usage_line(None)
+            );
+
+            for subcommand in SUBCOMMANDS {
+                let _ = write!(help, "\n    {}\n        {}\n", subcommand.usage, subcommand.about);
+            }
+
+            help.push_str("\nFLAGS:");
+            for spec in FLAGS {
+                write_flag(&mut help, spec);
+            }
+
+            let _ = write!(
+                help,
+                "\n    -V, --version\n        Display the version of tensorman\n\n{}",
+                EXIT_STATUS
+            );
+        }
+    }
+
+    help.truncate(help.trim_end().len());
+    help
+}
+
+const EXIT_STATUS: &str = "EXIT STATUS:
+    The exit status of the command is returned by the run subcommand. If the container
+    was killed by a signal, the status is 128 plus the signal number.
+
+    122  Invalid command-line usage
+    123  Configuration error
+    125  An error with docker was encountered";
+
+fn write_flag(help: &mut String, spec: &FlagSpec) {
+    help.push_str("\n    ");
+
+    if let Some(short) = spec.short {
+        let _ = write!(help, "-{}, ", short);
+    }
+
+    let _ = write!(help, "--{}", spec.long);
+
+    if let Some(value) = spec.value {
+        let _ = write!(help, " {}", value);
+    }
+
+    let _ = writeln!(help, "\n        {}", spec.about);
+}
+
+fn find_flag<'s, I>(mut specs: I, flag: &str, long: bool) -> Option<&'s FlagSpec>
+where
+    I: Iterator<Item = &'s FlagSpec>,
+{
+    specs.find(|spec| match long {
+        true => spec.long == flag,
+        false => spec.short.map_or(false, |short| flag == short.to_string()),
+    })
+}
+
+fn unknown_flag(subcommand: &'static Subcommand, flag: &str, long: bool) -> Error {
+    let argument = [if long { "--" } else { "-" }, flag].concat();
+
+    let why = if find_flag(FLAGS.iter(), flag, long).is_some() {
+        format!("the {} flag is not accepted by the {} subcommand", argument, subcommand.name)
+    } else {
+        let why = format!("unknown flag '{}' for the {} subcommand", argument, subcommand.name);
+        match suggest(flag, subcommand.flag_specs().map(|spec| spec.long)) {
+            Some(suggestion) => format!("{}; did you mean '--{}'?", why, suggestion),
+            None => why,
+        }
+    };
+
+    usage(Some(subcommand), why)
+}
+
+fn subcommand_names() -> impl Iterator<Item = &'static str> {
+    SUBCOMMANDS.iter().map(|subcommand| subcommand.name)
+}
+
+fn with_suggestion<'a, I>(why: String, needle: &str, candidates: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    match suggest(needle, candidates) {
+        Some(suggestion) => format!("{}; did you mean '{}'?", why, suggestion),
+        None => why,
+    }
+}
+
+fn usage<S: Into<String>>(subcommand: Option<&'static Subcommand>, why: S) -> Error {
+    Error::ArgumentUsage(subcommand.map(|subcommand| subcommand.name), anyhow!(why.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> Result<Cli<'static>, Error> {
+        let arguments: Vec<String> = arguments.iter().map(|&argument| argument.into()).collect();
+        Cli::parse(Box::leak(arguments.into_boxed_slice()))
+    }
+
+    fn invalid(arguments: &[&str]) -> bool {
+        matches!(parse(arguments), Err(Error::ArgumentUsage(..)))
+    }
+
+    fn run(arguments: &[&str]) -> (&'static str, Vec<&'static str>) {
+        match parse(arguments).unwrap().command {
+            Command::Run { cmd, args } => (cmd, args),
+            command => panic!("expected a run command, not {:?}", command),
+        }
+    }
+
+    #[test]
+    fn run_passes_arguments_after_the_command() {
+        assert_eq!(run(&["run", "python", "-v", "--gpu"]), ("python", vec!["-v", "--gpu"]));
+        assert_eq!(run(&["run", "bash", "-c", "--", "x"]), ("bash", vec!["-c", "--", "x"]));
+    }
+
+    #[test]
+    fn run_separator_ends_flags() {
+        assert_eq!(run(&["run", "--", "-weird", "x"]), ("-weird", vec!["x"]));
+        assert_eq!(run(&["run", "--gpu", "python", "--", "-u"]), ("python", vec!["-u"]));
+    }
+
+    #[test]
+    fn image_overrides() {
+        let cli = parse(&["+2.3", "run", "bash"]).unwrap();
+        assert_eq!(cli.tag, Some("2.3"));
+
+        let cli = parse(&["=custom", "run", "bash"]).unwrap();
+        assert_eq!(cli.container, Some("custom"));
+    }
+
+    #[test]
+    fn flag_values() {
+        let flags =
+            parse(&["run", "--port=8888:8888", "-p", "6006", "--docker-cmd", "podman", "bash"])
+                .unwrap()
+                .flags;
+        assert_eq!(flags.ports, ["8888:8888", "6006"]);
+        assert_eq!(flags.docker_cmd, Some("podman"));
+
+        let flags = parse(&["run", "--docker-cmd=podman", "bash"]).unwrap().flags;
+        assert_eq!(flags.docker_cmd, Some("podman"));
+    }
+
+    #[test]
+    fn invalid_usage() {
+        assert!(invalid(&[]));
+        assert!(invalid(&["bogus"]));
+        assert!(invalid(&["-x", "run", "bash"]));
+        assert!(invalid(&["run"]));
+        assert!(invalid(&["run", "--port"]));
+        assert!(invalid(&["run", "--gpu=yes", "bash"]));
+        assert!(invalid(&["list", "--force"]));
+    }
+
+    #[test]
+    fn env_flag() {
+        let flags = parse(&["run", "--env", "A=1", "--env=HOME", "bash"]).unwrap().flags;
+        assert_eq!(flags.env, [("A", Some("1")), ("HOME", None)]);
+
+        assert!(invalid(&["run", "--env", "1A=b", "bash"]));
+    }
+
+    #[test]
+    fn print_command_is_a_dry_run() {
+        let cli = parse(&["print-command", "run", "bash"]).unwrap();
+        assert!(cli.flags.dry_run);
+        assert!(matches!(cli.command, Command::Run { cmd: "bash", .. }));
+
+        assert!(invalid(&["print-command", "list"]));
+    }
+
+    #[test]
+    fn verbosity() {
+        let verbosity = |arguments: &[&str]| parse(arguments).unwrap().flags.verbosity;
+
+        assert_eq!(verbosity(&["pull"]), None);
+        assert_eq!(verbosity(&["pull", "-v"]), Some(Level::Verbose));
+        assert_eq!(verbosity(&["pull", "-vv"]), Some(Level::Debug));
+        assert_eq!(verbosity(&["pull", "-v", "--verbose"]), Some(Level::Debug));
+        assert_eq!(verbosity(&["pull", "-q"]), Some(Level::Quiet));
+        assert!(invalid(&["list", "-vx"]));
+    }
+
+    #[test]
+    fn verbosity_before_subcommand() {
+        let verbosity = |arguments: &[&str]| parse(arguments).unwrap().flags.verbosity;
+
+        assert_eq!(verbosity(&["-v", "pull"]), Some(Level::Verbose));
+        assert_eq!(verbosity(&["-v", "pull", "-v"]), Some(Level::Debug));
+        assert_eq!(verbosity(&["+2.3", "-vv", "run", "bash"]), Some(Level::Debug));
+        assert_eq!(verbosity(&["--quiet", "list"]), Some(Level::Quiet));
+        assert!(invalid(&["-v"]));
+    }
+}
@@ -1,132 +1,1630 @@
 use crate::{
+    cli::ConfigAction,
+    engine::EngineKind,
+    home::HomeMode,
     image::{ImageBuf, ImageSourceBuf, TagVariants},
-    misc::walk_parent_directories,
+    log::{self, Level},
+    misc::{expand_path, line_column, private_directory, suggest, walk_parent_directories},
+    runtime::{MountMode, ProjectRoot, TtyMode, Volume, Workspace},
+    secret::Secret,
+    toolchain,
 };
 
 use anyhow::Context;
-use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use serde::{
+    de::{
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize, Deserializer,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::{self, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use toml_edit::{DocumentMut, ImDocument, InlineTable, Item, Key, Table, Value};
 use xdg::BaseDirectories;
 
+/// Proxy settings, which are always passed through to the container when they are set.
+const PROXY_VARIABLES: &[&str] =
+    &["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY", "http_proxy", "https_proxy", "no_proxy"];
+
+/// The system-wide configuration file, which is shared by every user.
+const SYSTEM_PATH: &str = "/etc/tensorman/config.toml";
+
+/// The type of value that a configuration key holds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    String,
+    Bool,
+    List,
+    Table,
+}
+
+/// Every key which may be set in a configuration file.
+pub const KEYS: &[(&str, Kind)] = &[
+    ("datasets", Kind::List),
+    ("docker_flags", Kind::List),
+    ("env", Kind::Table),
+    ("engine", Kind::String),
+    ("env_passthrough", Kind::List),
+    ("fix_ownership_after_root", Kind::Bool),
+    ("home", Kind::String),
+    ("image", Kind::String),
+    ("mount_mode", Kind::String),
+    ("namespace", Kind::String),
+    ("ports", Kind::List),
+    ("project_root", Kind::String),
+    ("reset", Kind::List),
+    ("secrets", Kind::Table),
+    ("tag", Kind::String),
+    ("tty", Kind::String),
+    ("variants", Kind::List),
+    ("volumes", Kind::Table),
+];
+
+/// The kind of value held by a configuration key, if the key exists.
+pub fn key_kind(key: &str) -> Option<Kind> {
+    KEYS.iter().find(|(name, _)| *name == key).map(|&(_, kind)| kind)
+}
+
+/// Selects which configuration file is read or modified by the `config` subcommand.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// The user-wide configuration file.
+    User,
+    /// The `Tensorman.toml` of the current project.
+    Project,
+}
+
+impl Scope {
+    /// The path of the configuration file, which may not exist yet.
+    ///
+    /// If no project has been found, the project file is placed in the working directory.
+    pub fn path(self) -> anyhow::Result<PathBuf> {
+        match self {
+            Scope::User => user_path(),
+            Scope::Project => match local_path()? {
+                Some(path) => Ok(path),
+                None => std::env::current_dir()
+                    .context("failed to fetch the current working directory")
+                    .map(|dir| dir.join("Tensorman.toml")),
+            },
+        }
+    }
+}
+
+/// Describes where a setting was defined.
+#[derive(Debug, Clone)]
+pub enum Origin {
+    /// Given as an argument or flag on the command line.
+    CommandLine,
+    /// Pinned by a `tensorflow-toolchain` file.
+    Toolchain(PathBuf),
+    /// Defined by the `.env` file of a project.
+    DotEnv(PathBuf),
+    /// Defined by the `Tensorman.toml` of a project.
+    Project(PathBuf),
+    /// Defined by the user-wide configuration file.
+    User(PathBuf),
+    /// Defined by the system-wide configuration file.
+    System(PathBuf),
+    /// Not configured, so the built-in default is used.
+    Default,
+}
+
+impl Origin {
+    /// The configuration file which defined the setting, if it was defined by one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Origin::Toolchain(path)
+            | Origin::DotEnv(path)
+            | Origin::Project(path)
+            | Origin::User(path)
+            | Origin::System(path) => Some(path),
+            Origin::CommandLine | Origin::Default => None,
+        }
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::CommandLine => f.write_str("overridden on the command line"),
+            Origin::Toolchain(path) => write!(f, "overridden by '{}'", path.display()),
+            Origin::DotEnv(path) => write!(f, "set by the .env file at '{}'", path.display()),
+            Origin::Project(path) => write!(f, "set by the project config at '{}'", path.display()),
+            Origin::User(path) => write!(f, "set by the user config at '{}'", path.display()),
+            Origin::System(path) => write!(f, "set by the system config at '{}'", path.display()),
+            Origin::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// The effective configuration, after every layer of configuration has been merged.
+///
+/// Layers are applied in order of precedence, with later layers overriding earlier ones:
+/// the built-in defaults, the system config, the user config, the project's `Tensorman.toml`,
+/// the project's `tensorflow-toolchain`, and then the command line.
+///
+/// Scalar keys, such as `tag` and `tty`, are overridden by each layer which defines them.
+/// List keys, such as `docker_flags`, are appended to by each layer which defines them,
+/// and table keys, such as `env`, have their entries overridden by each layer which defines
+/// them, unless that layer names the key in its `reset` list, which discards inherited values.
 pub struct Config {
-    pub image:        Option<ImageBuf>,
-    pub docker_flags: Option<Vec<String>>,
+    pub image:                    ImageBuf,
+    pub docker_flags:             Vec<String>,
+    pub ports:                    Vec<String>,
+    pub env:                      BTreeMap<String, String>,
+    /// Names of host environment variables to pass through, which may end with `*`.
+    pub env_passthrough:          Vec<String>,
+    /// Volumes keyed by the path they are mounted at, with host paths already expanded.
+    pub volumes:                  BTreeMap<String, Volume>,
+    /// The host path of each dataset which has been defined, keyed by its name.
+    pub dataset_paths:            BTreeMap<String, PathBuf>,
+    /// The names of the datasets which are used by the project.
+    pub datasets:                 Vec<String>,
+    /// Secrets keyed by their name, with the paths of their files already expanded.
+    pub secrets:                  BTreeMap<String, Secret>,
+    pub tty:                      TtyMode,
+    pub project_root:             ProjectRoot,
+    pub mount_mode:               MountMode,
+    pub home:                     HomeMode,
+    pub engine:                   EngineKind,
+    /// The containerd namespace used by nerdctl, if one was configured.
+    pub namespace:                Option<String>,
+    /// Return files created by a `--root` run to the invoking user once it exits.
+    pub fix_ownership_after_root: bool,
+    /// The `Tensorman.toml` of the project, if one was found.
+    pub project_file:             Option<PathBuf>,
+    /// The `tensorflow-toolchain` of the project, if one was found.
+    pub toolchain_file:           Option<PathBuf>,
+    origins:                      Vec<(&'static str, Origin)>,
 }
 
 impl Config {
-    /// Read a config from either a local or user config
+    /// Reads and merges every layer of configuration, followed by the `command_line` layer.
+    pub fn read(command_line: RawConfig) -> anyhow::Result<Self> {
+        let mut config = Config {
+            image:                    ImageBuf {
+                variants: TagVariants::empty(),
+                source:   ImageSourceBuf::Tensorflow("latest".into()),
+                digest:   None,
+            },
+            docker_flags:             Vec::new(),
+            ports:                    Vec::new(),
+            env:                      BTreeMap::new(),
+            env_passthrough:          Vec::new(),
+            volumes:                  BTreeMap::new(),
+            dataset_paths:            BTreeMap::new(),
+            datasets:                 Vec::new(),
+            secrets:                  BTreeMap::new(),
+            tty:                      TtyMode::default(),
+            project_root:             ProjectRoot::default(),
+            mount_mode:               MountMode::default(),
+            home:                     HomeMode::default(),
+            engine:                   EngineKind::default(),
+            namespace:                None,
+            fix_ownership_after_root: false,
+            project_file:             None,
+            toolchain_file:           None,
+            origins:                  Vec::new(),
+        };
+
+        let system_path = PathBuf::from(SYSTEM_PATH);
+        if system_path.exists() {
+            config.merge(RawConfig::read_from(&system_path)?, Origin::System(system_path));
+        }
+
+        let user_path = user_path()?;
+        if user_path.exists() {
+            config.merge(RawConfig::read_from(&user_path)?, Origin::User(user_path));
+        }
+
+        if let Some(project_path) = local_path()? {
+            config
+                .merge(RawConfig::read_from(&project_path)?, Origin::Project(project_path.clone()));
+            config.project_file = Some(project_path);
+        }
+
+        if let Some(toolchain) = toolchain::toolchain_override()? {
+            config.merge(
+                RawConfig::from(&toolchain.image),
+                Origin::Toolchain(toolchain.path.clone()),
+            );
+            config.image.digest = toolchain.image.digest;
+            config.toolchain_file = Some(toolchain.path);
+        }
+
+        let dotenv = match config.project_dir() {
+            Some(dir) => dir.join(".env"),
+            None => std::env::current_dir()
+                .context("failed to fetch the current working directory")?
+                .join(".env"),
+        };
+
+        if dotenv.exists() {
+            let env = read_dotenv(&dotenv)?;
+            config.merge(
+                RawConfig { env: Some(env), ..RawConfig::default() },
+                Origin::DotEnv(dotenv),
+            );
+        }
+
+        config.merge(command_line, Origin::CommandLine);
+
+        debug!("resolved the image {} ({})", config.image.as_image(), config.origin("image"));
+        Ok(config)
+    }
+
+    /// The directory of the nearest `Tensorman.toml` or toolchain file, if either was found.
+    pub fn project_dir(&self) -> Option<&Path> {
+        self.project_file
+            .iter()
+            .chain(self.toolchain_file.iter())
+            .filter_map(|file| file.parent())
+            .max_by_key(|dir| dir.components().count())
+    }
+
+    /// The directory which is mounted into the container, as selected by `project_root`
+    /// and `mount_mode`.
+    pub fn workspace(&self) -> anyhow::Result<Workspace> {
+        let root = match self.project_root {
+            ProjectRoot::Project => self.project_dir(),
+            ProjectRoot::Here => None,
+        };
+
+        Workspace::new(root, self.mount_mode)
+    }
+
+    /// The environment of the container: the proxy settings and the variables named by
+    /// `env_passthrough` are taken from the host, and are overridden by `env`.
+    pub fn environment(&self) -> BTreeMap<String, String> {
+        let passed = |name: &str| {
+            PROXY_VARIABLES.contains(&name)
+                || self.env_passthrough.iter().any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => pattern == name,
+                })
+        };
+
+        let mut environment: BTreeMap<String, String> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(name, _)| passed(name))
+            .collect();
+
+        environment.extend(self.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        environment
+    }
+
+    /// Every volume to mount, including the datasets used by the project, which are mounted
+    /// read-only at `/data/<name>`.
+    pub fn volumes(&self) -> anyhow::Result<BTreeMap<String, Volume>> {
+        let mut volumes = self.volumes.clone();
+
+        for name in &self.datasets {
+            ensure!(
+                !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+                "'{}' is not a valid dataset name",
+                name
+            );
+
+            let host = self.dataset_paths.get(name).ok_or_else(|| {
+                let why = format!("the dataset '{}' is not defined in any [datasets] table", name);
+                match suggest(name, self.dataset_paths.keys().map(String::as_str)) {
+                    Some(suggestion) => anyhow!("{}; did you mean '{}'?", why, suggestion),
+                    None => anyhow!(why),
+                }
+            })?;
+
+            ensure!(
+                host.exists(),
+                "the path {} of the dataset '{}' does not exist",
+                host.display(),
+                name
+            );
+
+            let target = format!("/data/{}", name);
+            ensure!(
+                !volumes.contains_key(&target),
+                "the dataset '{}' and a volume are both mounted at '{}'",
+                name,
+                target
+            );
+
+            let volume = Volume {
+                host:              host.clone(),
+                read_only:         true,
+                create_if_missing: false,
+            };
+            volumes.insert(target, volume);
+        }
+
+        Ok(volumes)
+    }
+
+    /// Displays every defined dataset, whether its path exists, and whether it is used.
+    pub fn list_datasets(&self) {
+        let mut table = tabular::Table::new("{:<}  {:<}  {:<}  {:<}");
+        table.add_row(
+            tabular::Row::new()
+                .with_cell("NAME")
+                .with_cell("PATH")
+                .with_cell("STATUS")
+                .with_cell("USED"),
+        );
+
+        for (name, path) in &self.dataset_paths {
+            let status = if path.exists() { "found" } else { "missing" };
+            let used = if self.datasets.contains(name) { "yes" } else { "no" };
+            table.add_row(
+                tabular::Row::new()
+                    .with_cell(name)
+                    .with_cell(path.display())
+                    .with_cell(status)
+                    .with_cell(used),
+            );
+        }
+
+        for name in self.datasets.iter().filter(|name| !self.dataset_paths.contains_key(*name)) {
+            table.add_row(
+                tabular::Row::new()
+                    .with_cell(name)
+                    .with_cell("")
+                    .with_cell("undefined")
+                    .with_cell("yes"),
+            );
+        }
+
+        print!("{}", table);
+    }
+
+    /// Where the effective value of a key was defined.
     ///
-    /// The local config takes precedence over the user config.
-    /// If neither exists, a default config is returned.
-    pub fn read() -> anyhow::Result<Self> { RawConfig::read().map(Self::from) }
+    /// List keys may have been defined by several layers, of which the last is returned.
+    pub fn origin(&self, key: &str) -> &Origin {
+        self.origins
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map_or(&Origin::Default, |(_, origin)| origin)
+    }
 
-    /// Write a config to the external configuration path
-    pub fn write(&self) -> anyhow::Result<()> { RawConfig::from(self).write() }
-}
+    /// Every layer which defined the effective value of a key.
+    pub fn origins(&self, key: &str) -> Vec<&Origin> {
+        let origins: Vec<&Origin> = self
+            .origins
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, origin)| origin)
+            .collect();
+
+        if origins.is_empty() {
+            vec![&Origin::Default]
+        } else {
+            origins
+        }
+    }
+
+    /// The effective value of each key, and the layers which defined them.
+    pub fn values(&self) -> Vec<(&'static str, Value, Vec<&Origin>)> {
+        let (source_key, source) = match &self.image.source {
+            ImageSourceBuf::Container(image) => ("image", image),
+            ImageSourceBuf::Tensorflow(tag) => ("tag", tag),
+        };
+
+        let variants = <Vec<String>>::from(self.image.variants);
+
+        vec![
+            (source_key, Value::from(&**source), self.origins("image")),
+            ("variants", Value::Array(variants.iter().collect()), self.origins("variants")),
+            (
+                "docker_flags",
+                Value::Array(self.docker_flags.iter().collect()),
+                self.origins("docker_flags"),
+            ),
+            ("ports", Value::Array(self.ports.iter().collect()), self.origins("ports")),
+            ("env", Value::InlineTable(self.env.iter().collect()), self.origins("env")),
+            (
+                "env_passthrough",
+                Value::Array(self.env_passthrough.iter().collect()),
+                self.origins("env_passthrough"),
+            ),
+            (
+                "volumes",
+                Value::InlineTable(
+                    self.volumes
+                        .iter()
+                        .map(|(target, volume)| {
+                            let mut table = InlineTable::new();
+                            table.insert("host", Value::from(&*volume.host.to_string_lossy()));
+                            table.insert("read_only", Value::from(volume.read_only));
+                            table
+                                .insert("create_if_missing", Value::from(volume.create_if_missing));
+                            (target, Value::InlineTable(table))
+                        })
+                        .collect(),
+                ),
+                self.origins("volumes"),
+            ),
+            ("datasets", Value::Array(self.datasets.iter().collect()), self.origins("datasets")),
+            // The paths of datasets are also set by `datasets`, but are listed separately
+            // from the datasets which are used, so that every key is only listed once.
+            (
+                "dataset_paths",
+                Value::InlineTable(
+                    self.dataset_paths
+                        .iter()
+                        .map(|(name, path)| (name, Value::from(&*path.to_string_lossy())))
+                        .collect(),
+                ),
+                self.origins("dataset_paths"),
+            ),
+            (
+                "secrets",
+                Value::InlineTable(
+                    self.secrets
+                        .iter()
+                        .map(|(name, secret)| {
+                            // Values are never displayed, but the files holding them may be.
+                            let mut table = InlineTable::new();
+                            if secret.value.is_some() {
+                                table.insert("value", Value::from("***"));
+                            }
+                            if let Some(file) = &secret.file {
+                                table.insert("file", Value::from(&*file.to_string_lossy()));
+                            }
+                            if let Some(env) = &secret.env {
+                                table.insert("env", Value::from(env.as_str()));
+                            }
+                            (name, Value::InlineTable(table))
+                        })
+                        .collect(),
+                ),
+                self.origins("secrets"),
+            ),
+            ("tty", Value::from(self.tty.as_str()), self.origins("tty")),
+            ("project_root", Value::from(self.project_root.as_str()), self.origins("project_root")),
+            ("mount_mode", Value::from(self.mount_mode.as_str()), self.origins("mount_mode")),
+            ("home", Value::from(self.home.as_str()), self.origins("home")),
+            ("engine", Value::from(self.engine.as_str()), self.origins("engine")),
+            (
+                "namespace",
+                Value::from(self.namespace.as_deref().unwrap_or("default")),
+                self.origins("namespace"),
+            ),
+            (
+                "fix_ownership_after_root",
+                Value::from(self.fix_ownership_after_root),
+                self.origins("fix_ownership_after_root"),
+            ),
+        ]
+    }
 
-impl From<RawConfig> for Config {
-    fn from(raw: RawConfig) -> Self {
-        let RawConfig { docker_flags, image, tag, variants } = raw;
+    fn merge(&mut self, layer: RawConfig, origin: Origin) {
+        debug!("merging the configuration {}", origin);
 
-        let variants = variants.iter().flatten().map(String::as_str).collect::<TagVariants>();
+        let RawConfig {
+            datasets,
+            docker_flags,
+            engine,
+            env,
+            env_passthrough,
+            fix_ownership_after_root,
+            home,
+            image,
+            mount_mode,
+            namespace,
+            ports,
+            project_root,
+            reset,
+            secrets,
+            tag,
+            tty,
+            variants,
+            volumes,
+        } = layer;
+
+        for key in reset.iter().flatten() {
+            match key.as_str() {
+                "docker_flags" => self.docker_flags.clear(),
+                "env" => self.env.clear(),
+                "env_passthrough" => self.env_passthrough.clear(),
+                "ports" => self.ports.clear(),
+                "volumes" => self.volumes.clear(),
+                "secrets" => self.secrets.clear(),
+                // Only the datasets used are reset, as their paths are defined for the machine.
+                "datasets" => self.datasets.clear(),
+                _ => continue,
+            }
+
+            self.origins.retain(|(name, _)| name != key);
+        }
 
         let source = match (image, tag) {
-            (Some(image), _) => ImageSourceBuf::Container(image.into()),
-            (None, Some(tag)) => ImageSourceBuf::Tensorflow(tag.into()),
-            (None, None) => return Config { docker_flags, image: None },
+            (Some(image), _) => Some(ImageSourceBuf::Container(image.into())),
+            (None, Some(tag)) => Some(ImageSourceBuf::Tensorflow(tag.into())),
+            (None, None) => None,
         };
 
-        Config { docker_flags, image: Some(ImageBuf { variants, source }) }
+        // A digest only pins the image of the layer which set it.
+        if let Some(source) = source {
+            self.image.source = source;
+            self.image.digest = None;
+            self.set_origin("image", &origin);
+        }
+
+        if let Some(variants) = variants {
+            self.image.variants = variants;
+            self.set_origin("variants", &origin);
+        }
+
+        if let Some(tty) = tty {
+            self.tty = tty;
+            self.set_origin("tty", &origin);
+        }
+
+        if let Some(project_root) = project_root {
+            self.project_root = project_root;
+            self.set_origin("project_root", &origin);
+        }
+
+        if let Some(mount_mode) = mount_mode {
+            self.mount_mode = mount_mode;
+            self.set_origin("mount_mode", &origin);
+        }
+
+        if let Some(home) = home {
+            self.home = home;
+            self.set_origin("home", &origin);
+        }
+
+        if let Some(engine) = engine {
+            self.engine = engine;
+            self.set_origin("engine", &origin);
+        }
+
+        if let Some(namespace) = namespace {
+            self.namespace = Some(namespace);
+            self.set_origin("namespace", &origin);
+        }
+
+        if let Some(fix) = fix_ownership_after_root {
+            self.fix_ownership_after_root = fix;
+            self.set_origin("fix_ownership_after_root", &origin);
+        }
+
+        if let Some(ports) = ports.filter(|ports| !ports.is_empty()) {
+            self.ports.extend(ports);
+            self.origins.push(("ports", origin.clone()));
+        }
+
+        if let Some(env) = env.filter(|env| !env.is_empty()) {
+            self.env.extend(env);
+            self.origins.push(("env", origin.clone()));
+        }
+
+        if let Some(names) = env_passthrough.filter(|names| !names.is_empty()) {
+            self.env_passthrough.extend(names);
+            self.origins.push(("env_passthrough", origin.clone()));
+        }
+
+        // Host paths are expanded here, while the file which defined them is still known.
+        if let Some(volumes) = volumes.filter(|volumes| !volumes.is_empty()) {
+            let base = origin.path().and_then(Path::parent);
+            self.volumes.extend(volumes.into_iter().map(|(target, mut volume)| {
+                volume.host = expand_path(&volume.host, base);
+                (target, volume)
+            }));
+            self.origins.push(("volumes", origin.clone()));
+        }
+
+        match datasets {
+            Some(Datasets::Paths(paths)) if !paths.is_empty() => {
+                let base = origin.path().and_then(Path::parent);
+                self.dataset_paths
+                    .extend(paths.into_iter().map(|(name, path)| (name, expand_path(&path, base))));
+                self.origins.push(("dataset_paths", origin.clone()));
+            }
+            Some(Datasets::Used(names)) if !names.is_empty() => {
+                for name in names {
+                    if !self.datasets.contains(&name) {
+                        self.datasets.push(name);
+                    }
+                }
+
+                self.origins.push(("datasets", origin.clone()));
+            }
+            _ => (),
+        }
+
+        if let Some(secrets) = secrets.filter(|secrets| !secrets.is_empty()) {
+            let base = origin.path().and_then(Path::parent);
+            self.secrets.extend(secrets.into_iter().map(|(name, mut secret)| {
+                secret.file = secret.file.map(|file| expand_path(&file, base));
+                (name, secret)
+            }));
+            self.origins.push(("secrets", origin.clone()));
+        }
+
+        if let Some(docker_flags) = docker_flags.filter(|flags| !flags.is_empty()) {
+            self.docker_flags.extend(docker_flags);
+            self.origins.push(("docker_flags", origin));
+        }
+    }
+
+    fn set_origin(&mut self, key: &'static str, origin: &Origin) {
+        self.origins.retain(|(name, _)| *name != key);
+        self.origins.push((key, origin.clone()));
     }
 }
 
-#[derive(Deserialize, Default, Serialize)]
-struct RawConfig {
-    pub image:        Option<String>,
-    pub tag:          Option<String>,
-    pub variants:     Option<Vec<String>>,
-    pub docker_flags: Option<Vec<String>>,
+/// A single layer of configuration, as it is written in a configuration file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawConfig {
+    pub image:                    Option<String>,
+    pub tag:                      Option<String>,
+    pub variants:                 Option<TagVariants>,
+    pub docker_flags:             Option<Vec<String>>,
+    pub ports:                    Option<Vec<String>>,
+    pub env:                      Option<BTreeMap<String, String>>,
+    pub env_passthrough:          Option<Vec<String>>,
+    pub volumes:                  Option<BTreeMap<String, Volume>>,
+    pub datasets:                 Option<Datasets>,
+    pub secrets:                  Option<BTreeMap<String, Secret>>,
+    pub tty:                      Option<TtyMode>,
+    pub project_root:             Option<ProjectRoot>,
+    pub mount_mode:               Option<MountMode>,
+    pub home:                     Option<HomeMode>,
+    pub engine:                   Option<EngineKind>,
+    pub namespace:                Option<String>,
+    pub fix_ownership_after_root: Option<bool>,
+    /// Keys whose values from lower layers are discarded before this layer is applied.
+    #[serde(default, deserialize_with = "deserialize_reset")]
+    pub reset:                    Option<Vec<String>>,
 }
 
-impl RawConfig {
-    pub fn read() -> anyhow::Result<Self> {
-        let config_path = match local_path()? {
-            Some(config_path) => config_path,
-            None => {
-                let config_path = user_path()?;
+/// The `datasets` key, which is either a table which defines the host path of each dataset,
+/// as in the user config, or a list of the datasets used by a project.
+pub enum Datasets {
+    Paths(BTreeMap<String, PathBuf>),
+    Used(Vec<String>),
+}
 
-                if !config_path.exists() {
-                    return Ok(Self::default());
-                }
+impl<'de> Deserialize<'de> for Datasets {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DatasetsVisitor;
+
+        impl<'de> Visitor<'de> for DatasetsVisitor {
+            type Value = Datasets;
 
-                config_path
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of dataset names, or a table of dataset paths")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Datasets, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Datasets::Used)
             }
-        };
 
-        let data = fs::read_to_string(&config_path).with_context(|| {
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Datasets, A::Error> {
+                BTreeMap::deserialize(MapAccessDeserializer::new(map)).map(Datasets::Paths)
+            }
+        }
+
+        deserializer.deserialize_any(DatasetsVisitor)
+    }
+}
+
+/// Only keys which hold lists or tables may be reset.
+fn deserialize_reset<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    let keys = <Vec<String>>::deserialize(deserializer)?;
+
+    for key in &keys {
+        if !matches!(key_kind(key), Some(Kind::List | Kind::Table)) {
+            let names = KEYS
+                .iter()
+                .filter(|(_, kind)| matches!(kind, Kind::List | Kind::Table))
+                .map(|&(name, _)| name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(serde::de::Error::custom(format!(
+                "'{}' cannot be reset; only lists and tables may be reset: {}",
+                key, names
+            )));
+        }
+    }
+
+    Ok(Some(keys))
+}
+
+impl RawConfig {
+    fn read_from(config_path: &Path) -> anyhow::Result<Self> {
+        let data = fs::read_to_string(config_path).with_context(|| {
             format!("failed to read configuration file at {}", config_path.display())
         })?;
 
-        toml::from_str::<Self>(&*data).with_context(|| {
-            format!("failed to parse TOML in configuration file at {}", config_path.display())
-        })
+        Self::parse(&data, config_path)
     }
 
-    pub fn write(&self) -> anyhow::Result<()> {
-        let config_path = user_path()?;
+    /// Parses and validates the contents of the configuration file at `config_path`.
+    fn parse(data: &str, config_path: &Path) -> anyhow::Result<Self> {
+        let context =
+            || format!("failed to parse TOML in configuration file at {}", config_path.display());
 
-        println!("writing to configuration file at {}", config_path.display());
+        let document = ImDocument::parse(data).with_context(context)?;
+        let keys: Vec<&str> = KEYS.iter().map(|&(name, _)| name).collect();
+        check_keys(data, document.as_table(), &keys).with_context(context)?;
+        let config = toml::from_str::<Self>(data).with_context(context)?;
 
-        if !config_path.exists() {
-            let parent = config_path.parent().expect("config path without parent directory");
-            fs::create_dir_all(parent).with_context(|| {
-                format!(
-                    "failed to create the Tensorman configuration directory at {}",
-                    parent.display()
-                )
-            })?;
+        if config.tag.is_some() && config.image.is_some() {
+            return Err(anyhow!("only one of 'tag' or 'image' may be set")).with_context(context);
         }
 
-        let data = toml::to_string_pretty(self).expect("failed to serialize config");
-        fs::write(&config_path, data).with_context(|| {
+        Ok(config)
+    }
+
+    /// The image pinned by this layer, if it pins one.
+    fn image(&self) -> Option<ImageBuf> {
+        let source = match (&self.image, &self.tag) {
+            (Some(image), _) => ImageSourceBuf::Container(image.as_str().into()),
+            (None, Some(tag)) => ImageSourceBuf::Tensorflow(tag.as_str().into()),
+            (None, None) => return None,
+        };
+
+        Some(ImageBuf { variants: self.variants.unwrap_or_default(), source, digest: None })
+    }
+}
+
+impl<'a> From<&'a ImageBuf> for RawConfig {
+    fn from(image: &'a ImageBuf) -> Self {
+        let (image_, tag) = match &image.source {
+            ImageSourceBuf::Container(image) => (Some(String::from(&**image)), None),
+            ImageSourceBuf::Tensorflow(tag) => (None, Some(String::from(&**tag))),
+        };
+
+        RawConfig { image: image_, tag, variants: Some(image.variants), ..Self::default() }
+    }
+}
+
+/// Write the default image to the user-wide configuration file.
+///
+/// On a dry run, the file which would be written is printed instead.
+pub fn write_image(image: &ImageBuf, dry_run: bool) -> anyhow::Result<()> {
+    edit(&user_path()?, dry_run, |table| set_image(table, image))
+}
+
+/// Sets the default image, replacing the `tag` or `image` key which set the previous one.
+fn set_image(table: &mut Table, image: &ImageBuf) {
+    let (key, other, value) = match &image.source {
+        ImageSourceBuf::Container(image) => ("image", "tag", image),
+        ImageSourceBuf::Tensorflow(tag) => ("tag", "image", tag),
+    };
+
+    rename_key(table, other, key);
+    set_value(table, key, Value::from(&**value));
+
+    // Variants are always written so that an empty list overrides the system config.
+    let variants = <Vec<String>>::from(image.variants);
+    set_value(table, "variants", Value::Array(variants.iter().collect()));
+}
+
+/// Edits a configuration file in place, creating it if it does not exist.
+///
+/// Only the keys changed by `edit` are modified: comments, formatting, and every other key are
+/// preserved. The edited file must remain a valid configuration, or it will not be written.
+pub fn edit<F: FnOnce(&mut Table)>(
+    config_path: &Path,
+    dry_run: bool,
+    edit: F,
+) -> anyhow::Result<()> {
+    let mut document = read_document(config_path)?;
+
+    edit(document.as_table_mut());
+
+    let data = document.to_string();
+    RawConfig::parse(&data, config_path)
+        .context("the change would make the configuration invalid")?;
+
+    if dry_run {
+        println!("would write to configuration file at {}:\n{}", config_path.display(), data);
+        return Ok(());
+    }
+
+    write(config_path, &data)
+}
+
+/// Replaces the contents of a configuration file, creating its parent directory if needed.
+fn write(config_path: &Path, data: &str) -> anyhow::Result<()> {
+    if log::enabled(Level::Normal) {
+        println!("writing to configuration file at {}", config_path.display());
+    }
+
+    let parent = config_path.parent().expect("config path without parent directory");
+    fs::create_dir_all(parent).with_context(|| {
+        format!("failed to create the Tensorman configuration directory at {}", parent.display())
+    })?;
+
+    // A symlinked config, such as one kept by a dotfile manager, is replaced at its target.
+    let config_path = match fs::canonicalize(config_path) {
+        Ok(target) => target,
+        Err(_) => config_path.to_path_buf(),
+    };
+
+    // The config may hold secrets, so it keeps its mode, and is only readable by the user
+    // when it is created.
+    let mode = fs::metadata(&config_path).map_or(0o600, |metadata| metadata.mode() & 0o7777);
+
+    // Write to a temporary file first, so that an interrupted write cannot corrupt the config.
+    let temporary = config_path.with_extension("toml.tmp");
+    let _ = fs::remove_file(&temporary);
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temporary)
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .and_then(|_| fs::set_permissions(&temporary, Permissions::from_mode(mode)))
+        .and_then(|_| fs::rename(&temporary, &config_path))
+        .with_context(|| {
             format!(
                 "failed to write settings to Tensorman configuration file at {}",
                 config_path.display()
             )
         })
+}
+
+/// Rejects unknown keys, suggesting the key that may have been meant.
+pub fn check_keys(data: &str, table: &Table, keys: &[&str]) -> anyhow::Result<()> {
+    let errors: Vec<String> = table
+        .iter()
+        .filter(|(key, _)| !keys.contains(key))
+        .map(|(key, _)| {
+            let mut error = format!("unknown key '{}'", key);
+
+            if let Some(span) = table.key(key).and_then(Key::span) {
+                let (line, column) = line_column(data, span.start);
+                error = format!("{} at line {}, column {}", error, line, column);
+            }
+
+            match suggest(key, keys.iter().copied()) {
+                Some(suggestion) => format!("{}; did you mean '{}'?", error, suggestion),
+                None => error,
+            }
+        })
+        .collect();
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!(errors.join("\n    "))),
     }
 }
 
-impl<'a> From<&'a Config> for RawConfig {
-    fn from(config: &'a Config) -> Self {
-        let (image, tag, variants) = config.image.as_ref().map_or((None, None, None), |image| {
-            let variants = if image.variants.is_empty() {
-                None
-            } else {
-                Some(<Vec<String>>::from(image.variants))
+/// Renames a key, keeping its position, its value, and the comments around it.
+fn rename_key(table: &mut Table, from: &str, to: &str) {
+    if !table.contains_key(from) {
+        return;
+    }
+
+    table.remove(to);
+
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_owned()).collect();
+    for name in keys {
+        if let Some((key, item)) = table.remove_entry(&name) {
+            let key = match name == from {
+                true => Key::new(to).with_leaf_decor(key.leaf_decor().clone()),
+                false => key,
             };
 
-            let (image, tag) = match &image.source {
-                ImageSourceBuf::Container(image) => (Some(String::from(&**image)), None),
-                ImageSourceBuf::Tensorflow(tag) => (None, Some(String::from(&**tag))),
+            table.insert_formatted(&key, item);
+        }
+    }
+}
+
+/// Sets the value of a key, keeping the comments and whitespace around an existing value.
+fn set_value(table: &mut Table, key: &str, mut value: Value) {
+    if let Some(existing) = table.get(key).and_then(Item::as_value) {
+        *value.decor_mut() = existing.decor().clone();
+    }
+
+    table[key] = Item::Value(value);
+}
+
+/// Executes an action of the `config` subcommand.
+///
+/// Without a `scope`, values are read from the effective configuration, and written to the
+/// user-wide configuration file.
+pub fn run(
+    action: ConfigAction,
+    scope: Option<Scope>,
+    command_line: RawConfig,
+) -> anyhow::Result<()> {
+    match action {
+        ConfigAction::Get(key) => match scope {
+            Some(scope) => {
+                let path = scope.path()?;
+                let document = read_document(&path)?;
+                let value = document
+                    .get(key)
+                    .and_then(|item| item.clone().into_value().ok())
+                    .with_context(|| format!("'{}' is not set in {}", key, path.display()))?;
+
+                println!("{}", display_value(&value));
+            }
+            None => {
+                let config = Config::read(command_line)?;
+                let (_, value, _) = config
+                    .values()
+                    .into_iter()
+                    .find(|(name, ..)| *name == key)
+                    .with_context(|| format!("'{}' is not set", key))?;
+
+                println!("{}", display_value(&value));
+            }
+        },
+        ConfigAction::Set(key, values) => {
+            let value = match key_kind(key) {
+                Some(Kind::List) => Value::Array(values.iter().copied().collect()),
+                Some(Kind::Table) => {
+                    return Err(anyhow!(
+                        "'{}' is a table, which may be changed with `tensorman config edit`",
+                        key
+                    ))
+                }
+                Some(Kind::Bool) => match values[0] {
+                    "true" => Value::from(true),
+                    "false" => Value::from(false),
+                    value => {
+                        return Err(anyhow!("'{}' must be true or false, not '{}'", key, value))
+                    }
+                },
+                _ => Value::from(values[0]),
             };
 
-            (image, tag, variants)
-        });
+            let path = scope.unwrap_or(Scope::User).path()?;
+            ensure!(
+                !read_document(&path)?.get(key).map_or(false, Item::is_table),
+                "'{}' is a table in {}, which may be changed with `tensorman config edit`",
+                key,
+                path.display()
+            );
+
+            edit(&path, false, |table| {
+                // Only one of `tag` and `image` may be set, as `image` would take precedence.
+                match key {
+                    "tag" => rename_key(table, "image", "tag"),
+                    "image" => rename_key(table, "tag", "image"),
+                    _ => (),
+                }
+
+                set_value(table, key, value)
+            })?;
+        }
+        ConfigAction::Unset(key) => {
+            let path = scope.unwrap_or(Scope::User).path()?;
+            if read_document(&path)?.contains_key(key) {
+                edit(&path, false, |table| {
+                    table.remove(key);
+                })?;
+            }
+        }
+        ConfigAction::List { effective: true } => {
+            let config = Config::read(command_line)?;
+            let mut table = tabular::Table::new("{:<}  {:<}");
+
+            for (key, value, origins) in config.values() {
+                let origins: Vec<String> = origins.iter().map(ToString::to_string).collect();
+                table.add_row(
+                    tabular::Row::new()
+                        .with_cell(format!("{} = {}", key, value))
+                        .with_cell(format!("# {}", origins.join("; "))),
+                );
+            }
+
+            print!("{}", table);
+        }
+        ConfigAction::List { effective: false } => {
+            let document = read_document(&scope.unwrap_or(Scope::User).path()?)?;
+            let mut values = Vec::new();
+            flatten(&mut values, "", document.as_table());
+
+            for (key, value) in values {
+                println!("{} = {}", key, value);
+            }
+        }
+        ConfigAction::Edit => edit_interactively(&scope.unwrap_or(Scope::User).path()?)?,
+    }
+
+    Ok(())
+}
+
+/// Opens a copy of a configuration file in the user's editor, and saves it if it is valid.
+fn edit_interactively(config_path: &Path) -> anyhow::Result<()> {
+    let original = match config_path.exists() {
+        true => fs::read_to_string(config_path).with_context(|| {
+            format!("failed to read configuration file at {}", config_path.display())
+        })?,
+        false => String::new(),
+    };
+
+    // The config may hold secrets, so its copy is only readable by the user.
+    let directory = private_directory("tensorman-edit")?;
+    let copy = directory.join(config_path.file_name().unwrap_or_else(|| "config.toml".as_ref()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&copy)
+        .and_then(|mut file| file.write_all(original.as_bytes()))
+        .with_context(|| format!("failed to create a copy of the config at {}", copy.display()))?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("the EDITOR environment variable is empty")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(&copy)
+        .status()
+        .with_context(|| format!("failed to launch the editor '{}'", editor))?;
+
+    ensure!(
+        status.success(),
+        "the editor exited with {}; your changes are in {}",
+        status,
+        copy.display()
+    );
+
+    let data = fs::read_to_string(&copy)
+        .with_context(|| format!("failed to read the edited config at {}", copy.display()))?;
+
+    if data == original {
+        let _ = fs::remove_dir_all(&directory);
+        println!("no changes were made to {}", config_path.display());
+        return Ok(());
+    }
+
+    RawConfig::parse(&data, config_path).with_context(|| {
+        format!("the edited config is invalid; your changes are in {}", copy.display())
+    })?;
+
+    write(config_path, &data)?;
+
+    let _ = fs::remove_dir_all(&directory);
+    Ok(())
+}
+
+fn read_document(config_path: &Path) -> anyhow::Result<DocumentMut> {
+    if !config_path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    fs::read_to_string(config_path)
+        .with_context(|| format!("failed to read configuration file at {}", config_path.display()))?
+        .parse::<DocumentMut>()
+        .with_context(|| {
+            format!("failed to parse TOML in configuration file at {}", config_path.display())
+        })
+}
+
+/// Collects every value in a table as a dotted key and its value, without comments.
+fn flatten(values: &mut Vec<(String, String)>, prefix: &str, table: &Table) {
+    for (key, item) in table.iter() {
+        let key = [prefix, key].concat();
+        match item {
+            Item::Table(table) => flatten(values, &[&key, "."].concat(), table),
+            Item::Value(value) => values.push((key, value.clone().decorated("", "").to_string())),
+            item => values.push((key, item.to_string().trim().to_owned())),
+        }
+    }
+}
+
+/// Strings are displayed without quotes, and other values as they would be written in TOML.
+fn display_value(value: &Value) -> String {
+    match value.as_str() {
+        Some(string) => string.to_owned(),
+        None => value.clone().decorated("", "").to_string(),
+    }
+}
+
+/// Reads a `.env` file of `KEY=VALUE` lines, in the format used by docker compose.
+///
+/// Blank lines and comments are skipped, a leading `export` is ignored, and a value may be
+/// wrapped in single or double quotes.
+fn read_dotenv(path: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let data = fs::read_to_string(path)
+        .with_context(|| format!("failed to read the .env file at {}", path.display()))?;
+
+    parse_dotenv(&data, path)
+}
+
+/// Parses the contents of the `.env` file at `path`.
+fn parse_dotenv(data: &str, path: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut env = BTreeMap::new();
+    for (number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').with_context(|| {
+            format!("expected KEY=VALUE in the .env file at {}:{}", path.display(), number + 1)
+        })?;
 
-        RawConfig { image, tag, variants, docker_flags: config.docker_flags.clone() }
+        let key = key.trim();
+        ensure!(
+            is_variable_name(key),
+            "invalid variable name '{}' in the .env file at {}:{}",
+            key,
+            path.display(),
+            number + 1
+        );
+
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or_else(|| strip_comment(value));
+
+        env.insert(key.to_owned(), value.to_owned());
     }
+
+    Ok(env)
+}
+
+/// Drops an inline comment, which starts at a `#` preceded by whitespace, from an unquoted value.
+fn strip_comment(value: &str) -> &str {
+    value
+        .match_indices('#')
+        .find(|&(start, _)| value[..start].ends_with(char::is_whitespace))
+        .map_or(value, |(start, _)| value[..start].trim_end())
+}
+
+/// Whether `name` may be used as the name of an environment variable.
+pub fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Reads the image pinned by the configuration file at `path`, if it pins one.
+pub fn read_image(path: &Path) -> anyhow::Result<Option<ImageBuf>> {
+    RawConfig::read_from(path).map(|raw| raw.image())
 }
 
 fn local_path() -> anyhow::Result<Option<PathBuf>> {
     std::env::current_dir()
         .context("failed to fetch the current working directory")
-        .map(|dir| walk_parent_directories(&dir, "Tensorman.toml"))
+        .map(|dir| walk_parent_directories(&dir, &["Tensorman.toml"]))
 }
 
+/// The user-wide configuration file, which may not exist yet.
 fn user_path() -> anyhow::Result<PathBuf> {
-    BaseDirectories::with_prefix("tensorman")
+    Ok(BaseDirectories::with_prefix("tensorman")
         .context("failed to find the XDG base directory for tensorman")?
-        .place_config_file("config.toml")
-        .context("failed to fetch the user-wide Tensorman config path")
+        .get_config_home()
+        .join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> Origin { Origin::User(PathBuf::from("/home/user/.config/tensorman/config.toml")) }
+
+    fn project() -> Origin { Origin::Project(PathBuf::from("/home/user/project/Tensorman.toml")) }
+
+    /// Merges layers of configuration onto the built-in defaults.
+    fn merged(layers: &[(&str, Origin)]) -> Config {
+        let mut config = Config {
+            image:                    ImageBuf {
+                variants: TagVariants::empty(),
+                source:   ImageSourceBuf::Tensorflow("latest".into()),
+                digest:   None,
+            },
+            docker_flags:             Vec::new(),
+            ports:                    Vec::new(),
+            env:                      BTreeMap::new(),
+            env_passthrough:          Vec::new(),
+            volumes:                  BTreeMap::new(),
+            dataset_paths:            BTreeMap::new(),
+            datasets:                 Vec::new(),
+            secrets:                  BTreeMap::new(),
+            tty:                      TtyMode::default(),
+            project_root:             ProjectRoot::default(),
+            mount_mode:               MountMode::default(),
+            home:                     HomeMode::default(),
+            engine:                   EngineKind::default(),
+            namespace:                None,
+            fix_ownership_after_root: false,
+            project_file:             None,
+            toolchain_file:           None,
+            origins:                  Vec::new(),
+        };
+
+        for (data, origin) in layers {
+            config.merge(toml::from_str(data).unwrap(), origin.clone());
+        }
+
+        config
+    }
+
+    fn reference(config: &Config) -> String { String::from(&config.image.as_image()) }
+
+    #[test]
+    fn merge_overrides_scalars() {
+        let config = merged(&[
+            (
+                r#"
+                tag = "2.3.0"
+                variants = ["gpu"]
+                "#,
+                Origin::System(PathBuf::from(SYSTEM_PATH)),
+            ),
+            (r#"tag = "2.4.0""#, user()),
+            ("", project()),
+        ]);
+
+        assert_eq!(reference(&config), "tensorflow/tensorflow:2.4.0-gpu");
+        assert!(matches!(config.origin("image"), Origin::User(_)));
+        assert!(matches!(config.origin("variants"), Origin::System(_)));
+        assert!(matches!(config.origin("tty"), Origin::Default));
+
+        let config = merged(&[
+            (r#"tag = "2.4.0""#, user()),
+            (r#"image = "custom""#, project()),
+            (r#"tty = "never""#, Origin::CommandLine),
+        ]);
+
+        assert_eq!(reference(&config), "tensorman:custom");
+        assert!(matches!(config.tty, TtyMode::Never));
+        assert!(matches!(config.origin("image"), Origin::Project(_)));
+        assert!(matches!(config.origin("tty"), Origin::CommandLine));
+    }
+
+    #[test]
+    fn merge_appends_docker_flags() {
+        let config = merged(&[
+            (r#"docker_flags = ["--shm-size=1g"]"#, user()),
+            ("docker_flags = []", project()),
+            (r#"docker_flags = ["--init"]"#, Origin::CommandLine),
+        ]);
+
+        assert_eq!(config.docker_flags, ["--shm-size=1g", "--init"]);
+        assert!(matches!(config.origin("docker_flags"), Origin::CommandLine));
+    }
+
+    #[test]
+    fn merge_resets_inherited_values() {
+        let config = merged(&[
+            (
+                r#"
+                tag = "2.3.0"
+                docker_flags = ["--shm-size=1g"]
+                "#,
+                user(),
+            ),
+            (
+                r#"
+                reset = ["docker_flags"]
+                docker_flags = ["--init"]
+                "#,
+                project(),
+            ),
+        ]);
+
+        assert_eq!(config.docker_flags, ["--init"]);
+        assert!(matches!(config.origin("docker_flags"), Origin::Project(_)));
+        assert_eq!(reference(&config), "tensorflow/tensorflow:2.3.0");
+
+        let config = merged(&[
+            (r#"docker_flags = ["--shm-size=1g"]"#, user()),
+            (r#"reset = ["docker_flags"]"#, project()),
+        ]);
+
+        assert!(config.docker_flags.is_empty());
+        assert!(matches!(config.origin("docker_flags"), Origin::Default));
+    }
+
+    #[test]
+    fn merge_overrides_table_entries() {
+        let config = merged(&[
+            (
+                r#"
+                ports = ["8888"]
+                env = { A = "1", B = "2" }
+                volumes."/data" = { host = "data", read_only = true }
+                volumes."/models" = { host = "/srv/models" }
+                "#,
+                user(),
+            ),
+            (
+                r#"
+                ports = ["6006"]
+                env = { B = "3" }
+                volumes."/data" = { host = "/srv/data" }
+                "#,
+                project(),
+            ),
+        ]);
+
+        assert_eq!(config.ports, ["8888", "6006"]);
+        assert_eq!(config.env["A"], "1");
+        assert_eq!(config.env["B"], "3");
+        assert_eq!(config.volumes["/data"].host, Path::new("/srv/data"));
+        assert!(!config.volumes["/data"].read_only);
+        assert_eq!(config.volumes["/models"].host, Path::new("/srv/models"));
+        assert_eq!(config.origins("env").len(), 2);
+    }
+
+    #[test]
+    fn merge_expands_host_paths_against_their_file() {
+        let config = merged(&[(r#"volumes."/data" = { host = "data" }"#, user())]);
+        assert_eq!(config.volumes["/data"].host, Path::new("/home/user/.config/tensorman/data"));
+    }
+
+    #[test]
+    fn merge_resets_tables() {
+        let config = merged(&[
+            (
+                r#"
+                ports = ["8888"]
+                env = { A = "1" }
+                "#,
+                user(),
+            ),
+            (
+                r#"
+                reset = ["env", "ports"]
+                env = { B = "2" }
+                "#,
+                project(),
+            ),
+        ]);
+
+        assert!(config.ports.is_empty());
+        assert_eq!(config.env.keys().collect::<Vec<_>>(), ["B"]);
+        assert!(matches!(config.origins("env")[..], [Origin::Project(_)]));
+    }
+
+    #[test]
+    fn merge_resets_used_datasets_only() {
+        let config = merged(&[
+            (
+                r#"
+                [datasets]
+                imagenet = "/srv/imagenet"
+                mnist = "/srv/mnist"
+                "#,
+                user(),
+            ),
+            (r#"datasets = ["imagenet"]"#, project()),
+            (
+                r#"
+                reset = ["datasets"]
+                datasets = ["mnist"]
+                "#,
+                Origin::CommandLine,
+            ),
+        ]);
+
+        assert_eq!(config.datasets, ["mnist"]);
+        assert_eq!(config.dataset_paths.keys().collect::<Vec<_>>(), ["imagenet", "mnist"]);
+        assert!(matches!(config.origin("dataset_paths"), Origin::User(_)));
+        assert!(matches!(config.origins("datasets")[..], [Origin::CommandLine]));
+    }
+
+    const USER_CONFIG: &str = r#"# Pinned for the course.
+tag = "2.3.0" # the last release with Python 3.5
+variants = ["gpu"]
+
+# The data loaders need more shared memory.
+docker_flags = ["--shm-size=1g"]
+"#;
+
+    fn edited(data: &str, edit: impl FnOnce(&mut Table)) -> String {
+        let mut document = data.parse::<DocumentMut>().unwrap();
+        edit(document.as_table_mut());
+        document.to_string()
+    }
+
+    fn image(data: &str) -> ImageBuf { toml::from_str::<RawConfig>(data).unwrap().image().unwrap() }
+
+    #[test]
+    fn set_image_keeps_comments() {
+        let image = image(r#"tag = "2.4.0""#);
+        assert_eq!(
+            edited(USER_CONFIG, |table| set_image(table, &image)),
+            r#"# Pinned for the course.
+tag = "2.4.0" # the last release with Python 3.5
+variants = []
+
+# The data loaders need more shared memory.
+docker_flags = ["--shm-size=1g"]
+"#
+        );
+    }
+
+    #[test]
+    fn set_image_renames_tag_to_image() {
+        let image = image(
+            r#"
+            image = "custom:1"
+            variants = ["gpu", "python3"]
+            "#,
+        );
+
+        assert_eq!(
+            edited(USER_CONFIG, |table| set_image(table, &image)),
+            r#"# Pinned for the course.
+image = "custom:1" # the last release with Python 3.5
+variants = ["gpu", "python3"]
+
+# The data loaders need more shared memory.
+docker_flags = ["--shm-size=1g"]
+"#
+        );
+    }
+
+    #[test]
+    fn set_image_in_empty_config() {
+        let image = image(r#"tag = "2.4.0""#);
+        assert_eq!(
+            edited("", |table| set_image(table, &image)),
+            "tag = \"2.4.0\"\nvariants = []\n"
+        );
+    }
+
+    #[test]
+    fn rename_key_keeps_position() {
+        assert_eq!(
+            edited("a = 1\n# b\nb = 2\nc = 3\n", |table| rename_key(table, "b", "d")),
+            "a = 1\n# b\nd = 2\nc = 3\n"
+        );
+
+        // The key which is renamed to is replaced, and a missing key is not renamed.
+        assert_eq!(edited("a = 1\nb = 2\n", |table| rename_key(table, "a", "b")), "b = 1\n");
+        assert_eq!(edited("a = 1\n", |table| rename_key(table, "b", "a")), "a = 1\n");
+    }
+
+    #[test]
+    fn set_value_keeps_decor() {
+        assert_eq!(
+            edited("a  =  1   # one\nb = 2\n", |table| set_value(table, "a", Value::from(3))),
+            "a  =  3   # one\nb = 2\n"
+        );
+
+        assert_eq!(
+            edited("a = 1\n", |table| set_value(table, "b", Value::from("x"))),
+            "a = 1\nb = \"x\"\n"
+        );
+    }
+
+    /// A configuration file in the temporary directory, which is removed when dropped.
+    struct TemporaryConfig(PathBuf);
+
+    impl TemporaryConfig {
+        fn new(name: &str, data: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "tensorman-{}-{}.toml",
+                name,
+                std::process::id()
+            ));
+            fs::write(&path, data).unwrap();
+            TemporaryConfig(path)
+        }
+
+        fn read(&self) -> String { fs::read_to_string(&self.0).unwrap() }
+    }
+
+    impl Drop for TemporaryConfig {
+        fn drop(&mut self) { let _ = fs::remove_file(&self.0); }
+    }
+
+    #[test]
+    fn edit_writes_changes() {
+        let config = TemporaryConfig::new("edit", USER_CONFIG);
+        edit(&config.0, false, |table| set_value(table, "tty", Value::from("never"))).unwrap();
+        assert_eq!(config.read(), [USER_CONFIG, "tty = \"never\"\n"].concat());
+    }
+
+    #[test]
+    fn edit_rejects_invalid_changes() {
+        let config = TemporaryConfig::new("invalid", USER_CONFIG);
+        assert!(edit(&config.0, false, |table| set_value(table, "tty", Value::from("sometimes")))
+            .is_err());
+        assert_eq!(config.read(), USER_CONFIG);
+    }
+
+    #[test]
+    fn edit_writes_nothing_on_a_dry_run() {
+        let config = TemporaryConfig::new("dry-run", USER_CONFIG);
+        edit(&config.0, true, |table| set_value(table, "tty", Value::from("never"))).unwrap();
+        assert_eq!(config.read(), USER_CONFIG);
+    }
+
+    fn parse_error(data: &str) -> String {
+        match RawConfig::parse(data, Path::new("config.toml")) {
+            Ok(_) => panic!("expected {:?} to be rejected", data),
+            Err(error) => error.root_cause().to_string(),
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(
+            parse_error("tga = \"2.3\""),
+            "unknown key 'tga' at line 1, column 1; did you mean 'tag'?"
+        );
+        assert_eq!(
+            parse_error("tag = \"2.3\"\n\n  bogus = 1"),
+            "unknown key 'bogus' at line 3, column 3"
+        );
+    }
+
+    #[test]
+    fn unknown_variants() {
+        let error = parse_error("variants = [\"gpu\", \"pyhton3\"]");
+        assert!(error.contains("unknown variant 'pyhton3'; did you mean 'python3'?"), "{}", error);
+    }
+
+    fn dotenv(data: &str) -> anyhow::Result<Vec<(String, String)>> {
+        parse_dotenv(data, Path::new(".env")).map(|env| env.into_iter().collect())
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) { (key.to_owned(), value.to_owned()) }
+
+    #[test]
+    fn dotenv_values() {
+        let data = "# comment\n\nA=1 # note\nexport B = two words \nC=\nD=a#b\tc\t#note\n";
+        assert_eq!(
+            dotenv(data).unwrap(),
+            [pair("A", "1"), pair("B", "two words"), pair("C", ""), pair("D", "a#b\tc")]
+        );
+    }
+
+    #[test]
+    fn dotenv_quoted_values() {
+        let data = "A=\"a # b\"\nB='it=\"x\"'\nC=\"unterminated\nD='\nE=\"\"";
+        assert_eq!(
+            dotenv(data).unwrap(),
+            [
+                pair("A", "a # b"),
+                pair("B", "it=\"x\""),
+                pair("C", "\"unterminated"),
+                pair("D", "'"),
+                pair("E", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn dotenv_errors() {
+        let error = dotenv("A=1\nnot a pair\n").unwrap_err().to_string();
+        assert_eq!(error, "expected KEY=VALUE in the .env file at .env:2");
+
+        let error = dotenv("1A=1\n").unwrap_err().to_string();
+        assert_eq!(error, "invalid variable name '1A' in the .env file at .env:1");
+    }
+
+    #[test]
+    fn tag_and_image_are_exclusive() {
+        let path = Path::new("config.toml");
+        assert!(RawConfig::parse("tag = \"2.3\"", path).is_ok());
+        assert_eq!(
+            parse_error("tag = \"2.3\"\nimage = \"custom\""),
+            "only one of 'tag' or 'image' may be set"
+        );
+    }
 }
@@ -0,0 +1,325 @@
+mod apptainer;
+mod docker;
+mod nerdctl;
+mod podman;
+
+pub use self::{apptainer::Apptainer, docker::Docker, nerdctl::Nerdctl, podman::Podman};
+
+use crate::{
+    info::Info,
+    log::{self, Level},
+    runtime::{announce, timed, DockerImage, TtyMode, Workspace},
+};
+use serde::Deserialize;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Selects the container engine which images are run with.
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    /// The first of Docker, Podman, nerdctl, or Apptainer which is installed.
+    #[default]
+    Auto,
+    Docker,
+    Podman,
+    Nerdctl,
+    Apptainer,
+}
+
+impl EngineKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EngineKind::Auto => "auto",
+            EngineKind::Docker => "docker",
+            EngineKind::Podman => "podman",
+            EngineKind::Nerdctl => "nerdctl",
+            EngineKind::Apptainer => "apptainer",
+        }
+    }
+}
+
+/// A file or directory on the host which is mounted into the container.
+pub struct Mount {
+    pub host:      PathBuf,
+    pub target:    PathBuf,
+    pub read_only: bool,
+}
+
+/// A container to run, after the configuration has been resolved into what it mounts and
+/// the environment that it is given.
+pub struct Invocation<'a> {
+    /// The reference of the image, before it is qualified by the engine.
+    pub image:        String,
+    pub cmd:          &'a str,
+    pub args:         &'a [&'a str],
+    /// The full name of the container, including its `tensorman-` prefix.
+    pub name:         Option<&'a str>,
+    pub ports:        &'a [&'a str],
+    pub gpu:          bool,
+    pub as_root:      bool,
+    pub tty:          TtyMode,
+    /// The managed home directory on the host, or `None` if the workspace is the home.
+    pub home:         Option<&'a Path>,
+    /// The home directory within the container.
+    pub home_target:  &'a Path,
+    pub env:          Vec<(&'a str, &'a str)>,
+    /// Secrets injected as environment variables, whose values are passed through the
+    /// environment of the engine, so that they never appear on its command line.
+    pub secret_env:   Vec<(&'a str, &'a str)>,
+    pub mounts:       Vec<Mount>,
+    pub docker_flags: &'a [String],
+    pub workspace:    &'a Workspace,
+    /// A dry run only prints the command, so nothing may be written on the host.
+    pub dry_run:      bool,
+}
+
+/// The command-line interface of a container engine, where it differs from that of Docker.
+pub trait ContainerEngine {
+    /// The name of the engine, as it is selected by the `engine` key.
+    fn name(&self) -> &'static str;
+
+    /// A new invocation of the engine's command.
+    fn command(&self) -> Command;
+
+    /// The reference which the engine knows an image by, such as `tensorflow/tensorflow:latest`.
+    fn qualify(&self, reference: String) -> String { reference }
+
+    /// Adds the arguments which run the container of `invocation` as the invoking user.
+    fn user_args(&self, command: &mut Command, invocation: &Invocation) -> anyhow::Result<()>;
+
+    /// Adds the arguments which give the container access to every GPU.
+    fn gpu_args(&self, command: &mut Command);
+
+    /// Whether files created by root within a container are owned by root on the host, and
+    /// so must be returned to the invoking user.
+    fn root_owns_files(&self) -> bool { true }
+
+    /// The names of the containers which are running.
+    fn containers(&self) -> anyhow::Result<Vec<String>>;
+
+    /// The images which are installed.
+    fn images(&self) -> anyhow::Result<Vec<DockerImage>>;
+
+    /// The command which runs a container, as `docker run` would.
+    fn run_command(&self, invocation: &Invocation) -> anyhow::Result<Command> {
+        docker_run(self, invocation)
+    }
+
+    /// A command which must succeed before the image of a container can be run, if any.
+    ///
+    /// On a dry run, the command is only printed, so nothing may be written on the host.
+    fn prepare_command(&self, _reference: &str, _dry_run: bool) -> anyhow::Result<Option<Command>> {
+        Ok(None)
+    }
+
+    /// The command which fetches or updates an image.
+    ///
+    /// On a dry run, the command is only printed, so nothing may be written on the host.
+    fn pull_command(&self, reference: String, _dry_run: bool) -> anyhow::Result<Command> {
+        let mut command = self.command();
+        command.arg("pull");
+
+        if !log::enabled(Level::Normal) {
+            command.arg("--quiet");
+        }
+
+        command.arg(self.qualify(reference));
+        Ok(command)
+    }
+
+    /// The command which removes an installed image.
+    fn remove_command(&self, info: &Info, force: bool) -> anyhow::Result<Command> {
+        let mut command = self.command();
+        command.args(["rmi", &info.image_id]);
+
+        if force {
+            command.arg("--force");
+        }
+
+        Ok(command)
+    }
+
+    /// The command which saves a running container as the image `reference`.
+    fn commit_command(&self, container: &str, reference: String) -> anyhow::Result<Command> {
+        let mut command = self.command();
+        command.args(["commit", container, &self.qualify(reference)]);
+        Ok(command)
+    }
+}
+
+/// Creates the engine selected by `kind`, which is invoked as `program` if one is given.
+/// The `namespace` is the containerd namespace used by nerdctl.
+///
+/// When the engine is not configured, it is detected from the name of the `program`, or
+/// else from whichever of `docker`, `podman`, `nerdctl`, or `apptainer` is installed first.
+pub fn new(
+    kind: EngineKind,
+    program: Option<&str>,
+    namespace: Option<&str>,
+) -> Box<dyn ContainerEngine> {
+    let kind = match (kind, program) {
+        (EngineKind::Auto, Some(program)) => {
+            let name = Path::new(program).file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with("podman") {
+                EngineKind::Podman
+            } else if name.starts_with("nerdctl") {
+                EngineKind::Nerdctl
+            } else if name.starts_with("apptainer") || name.starts_with("singularity") {
+                EngineKind::Apptainer
+            } else {
+                EngineKind::Docker
+            }
+        }
+        (EngineKind::Auto, None) => {
+            [EngineKind::Docker, EngineKind::Podman, EngineKind::Nerdctl, EngineKind::Apptainer]
+                .iter()
+                .copied()
+                .find(|kind| find_program(kind.as_str()).is_some())
+                .unwrap_or(EngineKind::Docker)
+        }
+        (kind, _) => kind,
+    };
+
+    let engine: Box<dyn ContainerEngine> = match kind {
+        EngineKind::Podman => Box::new(Podman::new(program.unwrap_or("podman"))),
+        EngineKind::Nerdctl => {
+            Box::new(Nerdctl::new(program.unwrap_or("nerdctl"), namespace.map(String::from)))
+        }
+        EngineKind::Apptainer => Box::new(Apptainer::new(program.unwrap_or("apptainer"))),
+        _ => Box::new(Docker::new(program.unwrap_or("docker"))),
+    };
+
+    debug!("using the {} container engine", engine.name());
+    engine
+}
+
+/// The arguments of `docker run`, which are shared by every engine that is compatible with
+/// its command-line interface.
+fn docker_run<E: ContainerEngine + ?Sized>(
+    engine: &E,
+    invocation: &Invocation,
+) -> anyhow::Result<Command> {
+    let mut command = engine.command();
+    command.arg("run");
+
+    if invocation.as_root {
+        command.args(["-u", "root"]);
+    } else {
+        engine.user_args(&mut command, invocation)?;
+    }
+
+    if let Some(name) = invocation.name {
+        command.arg("--name").arg(name);
+    }
+
+    for port in invocation.ports {
+        command.arg("-p").arg(port);
+    }
+
+    if invocation.gpu {
+        engine.gpu_args(&mut command);
+    }
+
+    if let Some(home) = invocation.home {
+        command.arg("-v").arg(mount_argument(home, invocation.home_target, false));
+    }
+
+    command.arg("-e").arg(format!("HOME={}", invocation.home_target.display()));
+
+    for (key, value) in &invocation.env {
+        command.arg("-e").arg(format!("{}={}", key, value));
+    }
+
+    for (key, value) in &invocation.secret_env {
+        command.arg("-e").arg(key).env(key, value);
+    }
+
+    for mount in &invocation.mounts {
+        command.arg("-v").arg(mount_argument(&mount.host, &mount.target, mount.read_only));
+    }
+
+    command.args(invocation.docker_flags);
+
+    if let Some(flag) = invocation.tty.docker_flag() {
+        command.arg(flag);
+    }
+
+    let workspace = invocation.workspace;
+    command.args([
+        "--rm",
+        "-v",
+        &mount_argument(&workspace.host, &workspace.target, false),
+        "-w",
+        &workspace.workdir.display().to_string(),
+        &engine.qualify(invocation.image.clone()),
+        invocation.cmd,
+    ]);
+
+    command.args(invocation.args);
+    Ok(command)
+}
+
+/// The argument given to `docker run -v` or `apptainer --bind`.
+fn mount_argument(host: &Path, target: &Path, read_only: bool) -> String {
+    let mut argument = format!("{}:{}", host.display(), target.display());
+    if read_only {
+        argument.push_str(":ro");
+    }
+
+    argument
+}
+
+/// Registries which are prefixed to the references of images, when they are fully qualified.
+const REGISTRIES: &[&str] = &["docker.io/library/", "docker.io/", "localhost/"];
+
+/// The name of an image as Docker would show it, without the default registry.
+fn familiar_name(name: &str) -> &str {
+    REGISTRIES.iter().find_map(|registry| name.strip_prefix(registry)).unwrap_or(name)
+}
+
+/// Finds an executable in the directories of `PATH`.
+fn find_program(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Runs a command which queries the engine, and returns what it printed to stdout.
+///
+/// Queries change nothing, so they are run even on a dry run.
+fn output(mut command: Command) -> anyhow::Result<Vec<u8>> {
+    announce(&command, &[], false);
+
+    let output = timed(&mut command, Command::output)?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(anyhow::Error::msg(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+}
+
+/// Formats a size in bytes with four significant digits, as Docker does.
+fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "kB", "MB", "GB", "TB", "PB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit + 1 < UNITS.len() {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    let decimals = match size {
+        size if size < 10.0 => 3,
+        size if size < 100.0 => 2,
+        _ => 1,
+    };
+
+    let size = format!("{:.*}", decimals, size);
+    let size = size.trim_end_matches('0').trim_end_matches('.');
+    [size, UNITS[unit]].concat()
+}
@@ -0,0 +1,354 @@
+use super::{human_size, mount_argument, ContainerEngine, Invocation};
+use crate::{
+    info::Info,
+    log::{self, Level},
+    runtime::DockerImage,
+};
+use anyhow::Context;
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use xdg::BaseDirectories;
+
+/// The magic number of a SIF file, which follows its launch script.
+const SIF_MAGIC: &[u8] = b"SIF_MAGIC\0";
+
+/// Apptainer, or Singularity, which runs images that are converted to SIF files.
+///
+/// Apptainer has no daemon or image store, so images are converted from their Docker
+/// references into SIF files which are cached under `~/.cache/tensorman/sif`.
+pub struct Apptainer {
+    program: String,
+}
+
+impl Apptainer {
+    pub fn new(program: &str) -> Self { Self { program: program.to_owned() } }
+
+    /// The cache which holds the SIF file of every image, which may not exist yet.
+    fn cache() -> anyhow::Result<PathBuf> {
+        Ok(BaseDirectories::with_prefix("tensorman")
+            .context("failed to find the XDG base directory for tensorman")?
+            .get_cache_home()
+            .join("sif"))
+    }
+
+    /// Where the SIF file of an image is cached, such as `tensorflow/tensorflow/2.3.sif`.
+    fn sif(reference: &str) -> anyhow::Result<PathBuf> {
+        Ok(Self::cache()?.join(sif_file(reference)))
+    }
+}
+
+impl ContainerEngine for Apptainer {
+    fn name(&self) -> &'static str { "apptainer" }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        if !log::enabled(Level::Normal) {
+            command.arg("--quiet");
+        }
+
+        command
+    }
+
+    /// Apptainer always runs as the invoking user.
+    fn user_args(&self, _command: &mut Command, _invocation: &Invocation) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn gpu_args(&self, command: &mut Command) { command.arg("--nv"); }
+
+    /// Root within the container is only faked, so files are created as the invoking user.
+    fn root_owns_files(&self) -> bool { false }
+
+    /// Apptainer does not name containers, so none can be found by their names.
+    fn containers(&self) -> anyhow::Result<Vec<String>> { Ok(Vec::new()) }
+
+    /// Every SIF file in the cache, whose path gives the reference that it was pulled as.
+    fn images(&self) -> anyhow::Result<Vec<DockerImage>> {
+        let cache = Self::cache()?;
+        let mut images = Vec::new();
+        if !cache.exists() {
+            return Ok(images);
+        }
+
+        let mut directories = vec![cache.clone()];
+
+        while let Some(directory) = directories.pop() {
+            let entries = fs::read_dir(&directory).with_context(|| {
+                format!("failed to read the SIF cache at {}", directory.display())
+            })?;
+
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                let reference = path.strip_prefix(&cache).ok().and_then(sif_reference);
+                let (repository, tag) = match reference {
+                    Some(reference) => reference,
+                    None => continue,
+                };
+
+                let (id, created) = match read_header(&path) {
+                    Some(header) => header,
+                    None => {
+                        debug!("{} is not a SIF file", path.display());
+                        continue;
+                    }
+                };
+
+                let size = entry.metadata().map_or(0, |metadata| metadata.len());
+
+                images.push(DockerImage {
+                    Repository: repository,
+                    Tag:        tag,
+                    CreatedAt:  format_time(created),
+                    ID:         id,
+                    Size:       human_size(size),
+                });
+            }
+        }
+
+        images.sort_by(|a, b| (&a.Repository, &a.Tag).cmp(&(&b.Repository, &b.Tag)));
+        Ok(images)
+    }
+
+    /// Runs the cached SIF file of the image with `apptainer exec`.
+    ///
+    /// The container is given only the mounts and environment that Docker would give it.
+    /// Ports and docker flags have no equivalent, as Apptainer shares the host's network.
+    fn run_command(&self, invocation: &Invocation) -> anyhow::Result<Command> {
+        ensure!(invocation.name.is_none(), "apptainer does not give names to containers");
+
+        if !invocation.ports.is_empty() {
+            warn!("apptainer shares the network of the host, so ports are not mapped");
+        }
+
+        if !invocation.docker_flags.is_empty() {
+            warn!("apptainer does not accept docker flags, so docker_flags are ignored");
+        }
+
+        let workspace = invocation.workspace;
+        let mut command = self.command();
+        command.args(["exec", "--contain", "--cleanenv"]);
+
+        if invocation.as_root {
+            command.arg("--fakeroot");
+        }
+
+        if invocation.gpu {
+            self.gpu_args(&mut command);
+        }
+
+        // The workspace is mounted by `--home` when it is the home directory.
+        match invocation.home {
+            Some(home) => {
+                command.arg("--home").arg(mount_argument(home, invocation.home_target, false));
+                command.arg("--bind").arg(mount_argument(
+                    &workspace.host,
+                    &workspace.target,
+                    false,
+                ));
+            }
+            None => {
+                command.arg("--home").arg(mount_argument(
+                    &workspace.host,
+                    &workspace.target,
+                    false,
+                ));
+            }
+        }
+
+        for mount in &invocation.mounts {
+            command.arg("--bind").arg(mount_argument(&mount.host, &mount.target, mount.read_only));
+        }
+
+        for (key, value) in &invocation.env {
+            command.arg("--env").arg(format!("{}={}", key, value));
+        }
+
+        // Variables prefixed with `APPTAINERENV_` are kept by `--cleanenv`.
+        let prefix = match Path::new(&self.program).file_name() {
+            Some(name) if name.to_string_lossy().starts_with("singularity") => "SINGULARITYENV_",
+            _ => "APPTAINERENV_",
+        };
+
+        for (key, value) in &invocation.secret_env {
+            command.env([prefix, key].concat(), value);
+        }
+
+        command.arg("--pwd").arg(&workspace.workdir);
+        command.arg(Self::sif(&invocation.image)?);
+        command.arg(invocation.cmd);
+        command.args(invocation.args);
+        Ok(command)
+    }
+
+    /// Converts the image to a SIF file if it is not cached yet, as Docker pulls an image
+    /// which is missing when it is run.
+    fn prepare_command(&self, reference: &str, dry_run: bool) -> anyhow::Result<Option<Command>> {
+        let sif = Self::sif(reference)?;
+        if sif.exists() {
+            return Ok(None);
+        }
+
+        ensure!(
+            !reference.starts_with("tensorman:"),
+            "the image {} has no SIF file at {}",
+            reference,
+            sif.display()
+        );
+
+        self.pull_command(reference.to_owned(), dry_run).map(Some)
+    }
+
+    fn pull_command(&self, reference: String, dry_run: bool) -> anyhow::Result<Command> {
+        let sif = Self::sif(&reference)?;
+        if let Some(parent) = sif.parent().filter(|_| !dry_run) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        let mut command = self.command();
+        command.args(["pull", "--force"]);
+        command.arg(sif);
+        command.arg(["docker://", &reference].concat());
+        Ok(command)
+    }
+
+    fn remove_command(&self, info: &Info, force: bool) -> anyhow::Result<Command> {
+        let mut command = Command::new("rm");
+        if force {
+            command.arg("-f");
+        }
+
+        command.arg("--").arg(Self::sif(&info.reference())?);
+        Ok(command)
+    }
+
+    fn commit_command(&self, _container: &str, _reference: String) -> anyhow::Result<Command> {
+        Err(anyhow!("apptainer cannot save containers as images"))
+    }
+}
+
+/// The path of the SIF file of an image within the cache.
+fn sif_file(reference: &str) -> PathBuf {
+    let (name, digest) = match reference.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (reference, None),
+    };
+
+    let (repository, tag) = name.rsplit_once(':').unwrap_or((name, "latest"));
+    let mut file = tag.to_owned();
+    if let Some(digest) = digest {
+        file.push('@');
+        file.push_str(digest);
+    }
+
+    file.push_str(".sif");
+    Path::new(repository).join(file)
+}
+
+/// The repository and the tag of the image whose SIF file is at `file` within the cache.
+fn sif_reference(file: &Path) -> Option<(String, String)> {
+    let tag = file.file_name()?.to_str()?.strip_suffix(".sif")?;
+    let repository = file.parent()?.to_str()?;
+    Some((repository.to_owned(), tag.to_owned()))
+}
+
+/// Reads the ID and the creation time of a SIF file from its global header.
+fn read_header(path: &Path) -> Option<(String, i64)> {
+    let mut header = [0; 72];
+    File::open(path).and_then(|mut file| file.read_exact(&mut header)).ok()?;
+
+    if &header[32..42] != SIF_MAGIC {
+        return None;
+    }
+
+    let id = header[48..54].iter().map(|byte| format!("{:02x}", byte)).collect();
+    let mut created = [0; 8];
+    created.copy_from_slice(&header[64..72]);
+    Some((id, i64::from_le_bytes(created)))
+}
+
+/// Formats a Unix timestamp as Docker formats the creation time of an image.
+fn format_time(timestamp: i64) -> String {
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+
+    // Converts days since the epoch into a date of the proleptic Gregorian calendar.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} +0000 UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(reference: &str) -> String {
+        let (repo, tag) = sif_reference(&sif_file(reference)).unwrap();
+        Info {
+            repo:     repo.into(),
+            tag:      tag.into(),
+            image_id: "".into(),
+            size:     "".into(),
+            created:  "".into(),
+        }
+        .reference()
+    }
+
+    #[test]
+    fn sif_files_give_their_references() {
+        assert_eq!(
+            sif_file("tensorflow/tensorflow:2.3.0-gpu"),
+            Path::new("tensorflow/tensorflow/2.3.0-gpu.sif")
+        );
+        assert_eq!(
+            round_trip("tensorflow/tensorflow:2.3.0-gpu"),
+            "tensorflow/tensorflow:2.3.0-gpu"
+        );
+        assert_eq!(round_trip("tensorflow/tensorflow"), "tensorflow/tensorflow:latest");
+        assert_eq!(round_trip("localhost:5000/model:1"), "localhost:5000/model:1");
+    }
+
+    #[test]
+    fn sif_files_keep_digests() {
+        let reference = "tensorflow/tensorflow:2.3.0@sha256:0123456789abcdef";
+        assert_eq!(
+            sif_file(reference),
+            Path::new("tensorflow/tensorflow/2.3.0@sha256:0123456789abcdef.sif")
+        );
+        assert_eq!(round_trip(reference), reference);
+    }
+
+    #[test]
+    fn format_times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 +0000 UTC");
+        assert_eq!(format_time(951868800), "2000-03-01 00:00:00 +0000 UTC");
+        assert_eq!(format_time(1000000000), "2001-09-09 01:46:40 +0000 UTC");
+        assert_eq!(format_time(1582979696), "2020-02-29 12:34:56 +0000 UTC");
+        assert_eq!(format_time(-1), "1969-12-31 23:59:59 +0000 UTC");
+    }
+}
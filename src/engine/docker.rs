@@ -0,0 +1,124 @@
+use super::{output, ContainerEngine, Invocation};
+use crate::{
+    runtime::{announce, timed, DockerImage},
+    user::{self, ContainerUser},
+};
+use anyhow::Context;
+use serde::Deserialize;
+use std::process::Command;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct DockerContainer {
+    Names: String,
+}
+
+/// Docker, or any engine which is compatible with its command-line interface.
+pub struct Docker {
+    program: String,
+}
+
+impl Docker {
+    pub fn new(program: &str) -> Self { Self { program: program.to_owned() } }
+}
+
+impl ContainerEngine for Docker {
+    fn name(&self) -> &'static str { "docker" }
+
+    fn command(&self) -> Command { Command::new(&self.program) }
+
+    fn user_args(&self, command: &mut Command, invocation: &Invocation) -> anyhow::Result<()> {
+        host_user_args(self, command, invocation)
+    }
+
+    fn gpu_args(&self, command: &mut Command) { command.arg("--gpus=all"); }
+
+    fn containers(&self) -> anyhow::Result<Vec<String>> {
+        containers(self.command()).context("failed to fetch list of containers from Docker service")
+    }
+
+    fn images(&self) -> anyhow::Result<Vec<DockerImage>> {
+        images(self.command()).context("failed to fetch list of images from Docker service")
+    }
+}
+
+/// Runs as the host user's IDs, with the `passwd` and `group` files of the image extended
+/// with entries for the user, so that the user has a name and a home directory within the
+/// container.
+pub(super) fn host_user_args<E: ContainerEngine + ?Sized>(
+    engine: &E,
+    command: &mut Command,
+    invocation: &Invocation,
+) -> anyhow::Result<()> {
+    let user = ContainerUser::current();
+    let (passwd, group) = user.write_files(invocation.home_target, invocation.dry_run, || {
+        let reference = engine.qualify(invocation.image.clone());
+        let id = image_id(engine, &reference)?;
+
+        user::image_files(&id, |path| {
+            let mut command = engine.command();
+            command.args(["run", "--rm", "--entrypoint", "cat", &reference, path]);
+            Ok(String::from_utf8(output(command)?)?)
+        })
+    })?;
+
+    command.arg("-u").arg(user.spec());
+
+    for gid in &user.groups {
+        command.arg("--group-add").arg(gid.to_string());
+    }
+
+    command.arg("-v").arg(format!("{}:/etc/passwd:ro", passwd.display()));
+    command.arg("-v").arg(format!("{}:/etc/group:ro", group.display()));
+    Ok(())
+}
+
+/// The ID of an image, which is pulled first if it is not installed, as `docker run` would.
+fn image_id<E: ContainerEngine + ?Sized>(engine: &E, reference: &str) -> anyhow::Result<String> {
+    let inspect = || {
+        let mut command = engine.command();
+        command.args(["image", "inspect", "--format", "{{.Id}}", reference]);
+        output(command)
+    };
+
+    let id = match inspect() {
+        Ok(id) => id,
+        Err(_) => {
+            let mut pull = engine.pull_command(reference.to_owned(), false)?;
+            announce(&pull, &[], false);
+
+            let status = timed(&mut pull, Command::status)?;
+            ensure!(status.success(), "pulling the image {} failed with {}", reference, status);
+            inspect().with_context(|| format!("failed to inspect the image {}", reference))?
+        }
+    };
+
+    Ok(String::from_utf8_lossy(&id).trim().to_owned())
+}
+
+/// The names of running containers, as they are listed by `docker container ls`.
+pub(super) fn containers(mut command: Command) -> anyhow::Result<Vec<String>> {
+    command.args(["container", "ls", "--format", "{{json .}}"]);
+    let json = output(command)?;
+
+    let containers = serde_json::Deserializer::from_slice(&json)
+        .into_iter::<DockerContainer>()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(containers
+        .iter()
+        .flat_map(|container| container.Names.split(", "))
+        .map(String::from)
+        .collect())
+}
+
+/// The installed images, as they are listed by `docker images`.
+pub(super) fn images(mut command: Command) -> anyhow::Result<Vec<DockerImage>> {
+    command.args(["images", "--format", "{{json .}}"]);
+    let json = output(command)?;
+
+    serde_json::Deserializer::from_slice(&json)
+        .into_iter::<DockerImage>()
+        .collect::<Result<_, _>>()
+        .map_err(Into::into)
+}
@@ -0,0 +1,61 @@
+use super::{
+    docker::{containers, host_user_args, images},
+    familiar_name, ContainerEngine, Invocation,
+};
+use crate::runtime::DockerImage;
+use anyhow::Context;
+use nix::unistd::geteuid;
+use std::process::Command;
+
+/// nerdctl, which provides a Docker-compatible interface to containerd.
+pub struct Nerdctl {
+    program:   String,
+    /// The containerd namespace which images and containers are kept in, or the namespace
+    /// which nerdctl defaults to if it is `None`.
+    namespace: Option<String>,
+}
+
+impl Nerdctl {
+    pub fn new(program: &str, namespace: Option<String>) -> Self {
+        Self { program: program.to_owned(), namespace }
+    }
+}
+
+impl ContainerEngine for Nerdctl {
+    fn name(&self) -> &'static str { "nerdctl" }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        if let Some(namespace) = &self.namespace {
+            command.arg("--namespace").arg(namespace);
+        }
+
+        command
+    }
+
+    fn user_args(&self, command: &mut Command, invocation: &Invocation) -> anyhow::Result<()> {
+        host_user_args(self, command, invocation)
+    }
+
+    fn gpu_args(&self, command: &mut Command) { command.args(["--gpus", "all"]); }
+
+    /// Root within a rootless container is the invoking user on the host.
+    fn root_owns_files(&self) -> bool { geteuid().is_root() }
+
+    fn containers(&self) -> anyhow::Result<Vec<String>> {
+        containers(self.command()).context("failed to fetch list of containers from containerd")
+    }
+
+    /// nerdctl may list images by their fully qualified names, such as
+    /// `docker.io/tensorflow/tensorflow`, which are shortened as Docker would show them.
+    fn images(&self) -> anyhow::Result<Vec<DockerImage>> {
+        let mut images =
+            images(self.command()).context("failed to fetch list of images from containerd")?;
+
+        for image in &mut images {
+            image.Repository = familiar_name(&image.Repository).to_owned();
+        }
+
+        Ok(images)
+    }
+}
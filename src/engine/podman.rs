@@ -0,0 +1,167 @@
+use super::{familiar_name, human_size, output, ContainerEngine, Invocation};
+use crate::{runtime::DockerImage, user::ContainerUser};
+use anyhow::Context;
+use nix::unistd::geteuid;
+use serde::Deserialize;
+use std::process::Command;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct PodmanContainer {
+    #[serde(default)]
+    Names: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct PodmanImage {
+    Id:        String,
+    /// Every reference of the image, which is missing if the image is untagged.
+    #[serde(default)]
+    Names:     Vec<String>,
+    Size:      u64,
+    CreatedAt: String,
+}
+
+/// Podman, which runs containers without a daemon, and usually without root.
+pub struct Podman {
+    program: String,
+}
+
+impl Podman {
+    pub fn new(program: &str) -> Self { Self { program: program.to_owned() } }
+
+    /// The name of the OCI runtime which Podman runs containers with, such as `crun`.
+    fn runtime(&self) -> anyhow::Result<String> {
+        let mut command = self.command();
+        command.args(["info", "--format", "{{.Host.OCIRuntime.Name}}"]);
+        Ok(String::from_utf8(output(command)?)?.trim().to_owned())
+    }
+}
+
+impl ContainerEngine for Podman {
+    fn name(&self) -> &'static str { "podman" }
+
+    fn command(&self) -> Command { Command::new(&self.program) }
+
+    /// Images are fully qualified, as Podman may otherwise prompt for the registry of an
+    /// image, and commits images to `localhost`.
+    fn qualify(&self, reference: String) -> String {
+        let registry = reference.split_once('/').map(|(first, _)| first);
+        let qualified = registry.map_or(false, |first| {
+            first == "localhost" || first.contains('.') || first.contains(':')
+        });
+
+        if qualified {
+            reference
+        } else if reference.starts_with("tensorman:") {
+            ["localhost/", &reference].concat()
+        } else if registry.is_some() {
+            ["docker.io/", &reference].concat()
+        } else {
+            ["docker.io/library/", &reference].concat()
+        }
+    }
+
+    /// Podman maps the host user into the container itself. Its groups are kept by crun,
+    /// which is the only runtime that supports `keep-groups`, and are otherwise added by their
+    /// IDs, as Docker adds them.
+    fn user_args(&self, command: &mut Command, _invocation: &Invocation) -> anyhow::Result<()> {
+        command.arg("--userns=keep-id");
+
+        match self.runtime() {
+            Ok(runtime) if runtime == "crun" => {
+                command.args(["--group-add", "keep-groups"]);
+            }
+            runtime => {
+                debug!("podman does not run containers with crun: {:?}", runtime);
+                for gid in &ContainerUser::current().groups {
+                    command.arg("--group-add").arg(gid.to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// GPUs are exposed through the Container Device Interface.
+    fn gpu_args(&self, command: &mut Command) { command.args(["--device", "nvidia.com/gpu=all"]); }
+
+    /// Root within a rootless container is the invoking user on the host.
+    fn root_owns_files(&self) -> bool { geteuid().is_root() }
+
+    fn containers(&self) -> anyhow::Result<Vec<String>> {
+        let context = "failed to fetch list of containers from Podman";
+
+        let mut command = self.command();
+        command.args(["ps", "--format", "json"]);
+        let json = output(command).context(context)?;
+
+        let containers: Vec<PodmanContainer> = serde_json::from_slice(&json).context(context)?;
+        Ok(containers.into_iter().flat_map(|container| container.Names).collect())
+    }
+
+    /// Podman lists each image once, with all of its references, so an image is listed for
+    /// each of its references, as Docker would.
+    fn images(&self) -> anyhow::Result<Vec<DockerImage>> {
+        let context = "failed to fetch list of images from Podman";
+
+        let mut command = self.command();
+        command.args(["images", "--format", "json"]);
+        let json = output(command).context(context)?;
+
+        let images: Vec<PodmanImage> = serde_json::from_slice(&json).context(context)?;
+        let mut listed = Vec::new();
+
+        for image in images {
+            for name in &image.Names {
+                let name = familiar_name(name);
+
+                let (repository, tag) = match name.rsplit_once(':') {
+                    Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                    _ => (name, "latest"),
+                };
+
+                listed.push(DockerImage {
+                    Repository: repository.to_owned(),
+                    Tag:        tag.to_owned(),
+                    CreatedAt:  image.CreatedAt.clone(),
+                    ID:         image.Id.chars().take(12).collect(),
+                    Size:       human_size(image.Size),
+                });
+            }
+        }
+
+        Ok(listed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qualify(reference: &str) -> String { Podman::new("podman").qualify(reference.to_owned()) }
+
+    #[test]
+    fn qualifies_docker_hub_images() {
+        assert_eq!(qualify("ubuntu"), "docker.io/library/ubuntu");
+        assert_eq!(qualify("tensorflow/tensorflow:2.3"), "docker.io/tensorflow/tensorflow:2.3");
+    }
+
+    #[test]
+    fn qualifies_committed_images() {
+        assert_eq!(qualify("tensorman:custom"), "localhost/tensorman:custom");
+    }
+
+    #[test]
+    fn keeps_qualified_images() {
+        for reference in &[
+            "localhost/tensorman:custom",
+            "docker.io/library/ubuntu",
+            "ghcr.io/owner/image:1",
+            "registry:5000/image",
+        ] {
+            assert_eq!(qualify(reference), *reference);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::image::Image;
+use anyhow::Context;
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+use xdg::BaseDirectories;
+
+/// Where the managed home directory is mounted within the container.
+pub const TARGET: &str = "/home/tensorman";
+
+/// Selects the directory which is used as the home directory of the container.
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HomeMode {
+    /// A directory kept by tensorman for each image, under the XDG data directory.
+    #[default]
+    Managed,
+    /// The mounted project, so that caches and history are written into the project.
+    Workspace,
+}
+
+impl HomeMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HomeMode::Managed => "managed",
+            HomeMode::Workspace => "workspace",
+        }
+    }
+}
+
+/// The managed home directory of an image, which may not exist yet.
+///
+/// Homes are shared by every project which runs the same image, and are kept when the image
+/// is pinned to a different digest, so that caches survive an update of the image.
+pub fn path(image: &Image) -> anyhow::Result<PathBuf> {
+    let reference = String::from(&Image { digest: None, ..*image });
+    let name: String = reference
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+
+    let homes = BaseDirectories::with_prefix("tensorman")
+        .context("failed to find the XDG base directory for tensorman")?
+        .get_data_home()
+        .join("home");
+
+    Ok(homes.join(name))
+}
+
+/// Creates the managed home directory of an image, if it does not exist yet.
+///
+/// The directory must be created before it is mounted, or docker would create it as root.
+pub fn create(image: &Image) -> anyhow::Result<PathBuf> {
+    let path = path(image)?;
+    fs::create_dir_all(&path)
+        .with_context(|| format!("failed to create home directory at {}", path.display()))?;
+    Ok(path)
+}
+
+/// Removes the managed home directory of an image, along with everything in it.
+pub fn clean(image: &Image) -> anyhow::Result<()> {
+    let path = path(image)?;
+
+    if !path.exists() {
+        println!("no home directory exists at {}", path.display());
+        return Ok(());
+    }
+
+    fs::remove_dir_all(&path)
+        .with_context(|| format!("failed to remove home directory at {}", path.display()))?;
+
+    println!("removed home directory at {}", path.display());
+    Ok(())
+}
@@ -1,11 +1,17 @@
+use crate::{
+    engine::ContainerEngine,
+    misc::suggest,
+    runtime::{announce, timed},
+};
+use serde::{Deserialize, Deserializer};
 use std::{
     fmt::{self, Display},
-    io,
     process::Command,
+    str::FromStr,
 };
 
 bitflags::bitflags! {
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Default, Copy, Clone)]
     pub struct TagVariants: u8 {
         const GPU = 1;
         const PY3 = 1 << 1;
@@ -13,22 +19,44 @@ bitflags::bitflags! {
     }
 }
 
-impl<'a> std::iter::FromIterator<&'a str> for TagVariants {
-    fn from_iter<I>(iterator: I) -> Self
-    where
-        I: IntoIterator<Item = &'a str>,
-    {
-        let mut variants = TagVariants::empty();
-        for variant in iterator {
-            match variant {
-                "gpu" => variants |= TagVariants::GPU,
-                "python3" => variants |= TagVariants::PY3,
-                "jupyter" => variants |= TagVariants::JUPYTER,
-                _ => (),
-            }
+/// The name of each variant, along with the suffix it adds to the image tag.
+const VARIANT_NAMES: &[(&str, &str, TagVariants)] = &[
+    ("gpu", "gpu", TagVariants::GPU),
+    ("python3", "py3", TagVariants::PY3),
+    ("jupyter", "jupyter", TagVariants::JUPYTER),
+];
+
+impl FromStr for TagVariants {
+    type Err = anyhow::Error;
+
+    fn from_str(variant: &str) -> Result<Self, Self::Err> {
+        if let Some(&(_, _, flag)) = VARIANT_NAMES.iter().find(|(name, ..)| *name == variant) {
+            return Ok(flag);
         }
 
-        variants
+        // Variants are often mistaken for the suffix that they add to the tag.
+        let suggestion = VARIANT_NAMES
+            .iter()
+            .find(|(_, suffix, _)| *suffix == variant)
+            .map(|&(name, ..)| name)
+            .or_else(|| suggest(variant, VARIANT_NAMES.iter().map(|&(name, ..)| name)));
+
+        Err(match suggestion {
+            Some(suggestion) => {
+                anyhow!("unknown variant '{}'; did you mean '{}'?", variant, suggestion)
+            }
+            None => anyhow!("unknown variant '{}'; expected one of gpu, python3, jupyter", variant),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TagVariants {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Vec<String>>::deserialize(deserializer)?
+            .iter()
+            .map(|variant| variant.parse::<TagVariants>())
+            .collect::<Result<TagVariants, _>>()
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -56,13 +84,29 @@ impl From<TagVariants> for Vec<String> {
 pub struct ImageBuf {
     pub variants: TagVariants,
     pub source:   ImageSourceBuf,
+    /// Pins the exact image content, such as `sha256:...`, regardless of where the tag points.
+    pub digest:   Option<Box<str>>,
+}
+
+impl ImageBuf {
+    pub fn as_image(&self) -> Image {
+        Image {
+            variants: self.variants,
+            source:   match &self.source {
+                ImageSourceBuf::Container(container) => ImageSource::Container(container),
+                ImageSourceBuf::Tensorflow(tag) => ImageSource::Tensorflow(tag),
+            },
+            digest:   self.digest.as_deref(),
+        }
+    }
 }
 
 /// A description of a Tensorflow Docker image, identified by its tag and tag variants.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct Image<'a> {
     pub variants: TagVariants,
     pub source:   ImageSource<'a>,
+    pub digest:   Option<&'a str>,
 }
 
 #[derive(Debug)]
@@ -71,18 +115,28 @@ pub enum ImageSourceBuf {
     Tensorflow(Box<str>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum ImageSource<'a> {
     Container(&'a str),
     Tensorflow(&'a str),
 }
 
 impl<'a> Image<'a> {
-    pub fn pull(&self, docker_cmd: &str) -> io::Result<()> {
-        let mut command = Command::new(docker_cmd);
-        command.args(&["pull", &String::from(self)]);
-        eprintln!("{:?}", command);
-        command.status().map(|_| ())
+    pub fn pull(&self, engine: &dyn ContainerEngine, dry_run: bool) -> anyhow::Result<()> {
+        let mut command = engine.pull_command(String::from(self), dry_run)?;
+
+        if !announce(&command, &[], dry_run) {
+            return Ok(());
+        }
+
+        let status = timed(&mut command, Command::status)?;
+        ensure!(
+            status.success(),
+            "pulling the image {} failed with {}",
+            String::from(self),
+            status
+        );
+        Ok(())
     }
 }
 
@@ -107,6 +161,11 @@ impl<'a> From<&Image<'a>> for String {
                     }
                 }
 
+                if let Some(digest) = image.digest {
+                    buffer.push('@');
+                    buffer.push_str(digest);
+                }
+
                 buffer
             }
         }
@@ -135,6 +194,11 @@ impl<'a> Display for Image<'a> {
                     }
                 }
 
+                if let Some(digest) = self.digest {
+                    f.write_str("@")?;
+                    f.write_str(digest)?;
+                }
+
                 Ok(())
             }
         }
@@ -6,9 +6,13 @@ pub struct Info {
     pub tag:      Box<str>,
     pub image_id: Box<str>,
     pub size:     Box<str>,
+    pub created:  Box<str>,
 }
 
 impl Info {
+    /// The full reference of the image, such as `tensorflow/tensorflow:latest`.
+    pub fn reference(&self) -> String { [&*self.repo, ":", &*self.tag].concat() }
+
     /// Check if any of the string fields matches the `needle`.
     pub fn field_matches(&self, needle: &str) -> bool {
         match self.repo.as_ref() {
@@ -29,5 +33,6 @@ pub fn iterate_image_info(images: Vec<DockerImage>) -> impl Iterator<Item = Info
         tag:      image.Tag.into(),
         image_id: image.ID.into(),
         size:     image.Size.into(),
+        created:  image.CreatedAt.into(),
     })
 }
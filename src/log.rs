@@ -0,0 +1,92 @@
+use std::{
+    env,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// The environment variable which selects the verbosity, when no flag selects it.
+pub const VARIABLE: &str = "TENSORMAN_LOG";
+
+/// How much tensorman reports about what it is doing, on stderr.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    /// Only errors, and the output of docker itself.
+    Quiet,
+    /// Warnings, and messages about changes made to the system, such as configuration files
+    /// which are written.
+    Normal,
+    /// Every docker command, before it is run.
+    Verbose,
+    /// How the configuration was resolved, and how long each docker command took.
+    Debug,
+}
+
+const LEVELS: &[(&str, Level)] = &[
+    ("quiet", Level::Quiet),
+    ("normal", Level::Normal),
+    ("verbose", Level::Verbose),
+    ("debug", Level::Debug),
+];
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+impl Level {
+    /// The level selected by `TENSORMAN_LOG`, or the normal level if it is not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let value = match env::var(VARIABLE) {
+            Ok(value) if !value.is_empty() => value,
+            _ => return Ok(Level::Normal),
+        };
+
+        LEVELS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&value))
+            .map(|&(_, level)| level)
+            .ok_or_else(|| {
+                let names: Vec<&str> = LEVELS.iter().map(|&(name, _)| name).collect();
+                anyhow!("{} must be one of {}, not '{}'", VARIABLE, names.join(", "), value)
+            })
+    }
+
+    /// The level selected by the number of times `-v` was given.
+    pub fn verbose(count: usize) -> Self {
+        match count {
+            0 => Level::Normal,
+            1 => Level::Verbose,
+            _ => Level::Debug,
+        }
+    }
+}
+
+/// Sets the verbosity of every message which follows.
+pub fn set(level: Level) { LEVEL.store(level as u8, Ordering::Relaxed); }
+
+/// Whether messages of the given level are printed.
+pub fn enabled(level: Level) -> bool { LEVEL.load(Ordering::Relaxed) >= level as u8 }
+
+/// Prints a warning to stderr, unless the verbosity is `Level::Quiet`.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Normal) {
+            eprintln!("tensorman: warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Prints a message to stderr, if the verbosity is at least `Level::Verbose`.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a message to stderr, if the verbosity is `Level::Debug`.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!("debug: {}", format_args!($($arg)*));
+        }
+    };
+}
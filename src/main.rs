@@ -3,303 +3,317 @@ extern crate anyhow;
 #[macro_use]
 extern crate thiserror;
 
+// Declared first, so that its macros may be used by every other module.
+#[macro_use]
+mod log;
+
+mod cli;
 mod config;
+mod engine;
+mod home;
 mod image;
 mod info;
 mod misc;
+mod projects;
 mod runtime;
+mod secret;
+mod signal;
 mod toolchain;
+mod user;
 
 use anyhow::Context;
 
 use self::{
-    config::Config,
-    image::{Image, ImageBuf, ImageSource, ImageSourceBuf, TagVariants},
-    runtime::Runtime,
+    cli::{Cli, Command, HomeAction},
+    config::{Config, RawConfig},
+    home::HomeMode,
+    image::{ImageBuf, ImageSource, ImageSourceBuf, TagVariants},
+    runtime::{RunSpec, Runtime},
 };
 
-use std::{env::args, error::Error as _, process::exit};
+use std::{
+    collections::BTreeMap,
+    env::{self, args},
+    error::Error as _,
+    iter,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{exit, ExitStatus},
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("invalid command-line usage")]
-    ArgumentUsage(#[source] anyhow::Error),
+    ArgumentUsage(Option<&'static str>, #[source] anyhow::Error),
     #[error("configuration error")]
     Configure(#[source] anyhow::Error),
     #[error("an error with docker was encountered")]
     Docker(#[source] anyhow::Error),
+    #[error("container exited with status {0}")]
+    Exited(i32),
+    #[error("container killed by signal {}", signal_description(*.0))]
+    Killed(i32),
 }
 
-fn main_() -> Result<(), Error> {
-    let config = Config::read().map_err(Error::Configure)?;
-    let toolchain_override = toolchain::toolchain_override();
-
-    let (mut specific_container, mut tag, mut variants) =
-        toolchain_override.as_ref().or_else(|| config.image.as_ref()).map_or_else(
-            || (None, "latest", TagVariants::empty()),
-            |image| {
-                let (container, tag) = match &image.source {
-                    ImageSourceBuf::Container(container) => (Some(container.as_ref()), None),
-                    ImageSourceBuf::Tensorflow(tag) => (None, Some(tag.as_ref())),
-                };
-
-                (container, tag.unwrap_or("latest"), image.variants)
-            },
-        );
-
-    let arguments: Vec<String> = args().skip(1).collect();
-    let mut arguments = arguments.iter();
-
-    // Allow the first argument, if it begins with `+`, to override the tag.
-    let mut subcommand = arguments.next().and_then(|argument| {
-        if argument.starts_with('+') {
-            tag = &argument[1..];
-            arguments.next().map(String::as_str)
-        } else if argument.starts_with('=') {
-            specific_container = Some(&argument[1..]);
-            arguments.next().map(String::as_str)
-        } else {
-            Some(argument.as_str())
+impl Error {
+    /// Checks the exit status of a container, returning an error if it did not succeed.
+    ///
+    /// Docker reports a container killed by a signal as exiting with `128 + signal`, which
+    /// is interpreted the same way a shell would.
+    pub fn from_status(status: ExitStatus) -> Result<(), Self> {
+        match (status.code(), status.signal()) {
+            (Some(0), _) => Ok(()),
+            (Some(code), _) if code > 128 && code < 128 + 65 => Err(Error::Killed(code - 128)),
+            (Some(code), _) => Err(Error::Exited(code)),
+            (None, Some(signal)) => Err(Error::Killed(signal)),
+            (None, None) => Err(Error::Exited(1)),
         }
-    });
-
-    let subcommand = subcommand
-        .take()
-        .context("tensorman must be given a subcommand to execute")
-        .map_err(Error::ArgumentUsage)?;
-
-    let mut subcommand_args = Vec::new();
-
-    let mut as_root = false;
-    let mut force = false;
-
-    let mut flagged_variants = TagVariants::empty();
-
-    let mut name = None;
-    let mut ports = Vec::new();
-
-    let mut docker_cmd = "docker";
-
-    while let Some(argument) = arguments.next() {
-        match argument.as_str() {
-            "-h" | "--help" => help(),
-            "--" => break,
-            "-f" | "--force" => force = true,
-            "--gpu" => flagged_variants |= TagVariants::GPU,
-            "--https" => {}
-            "--docker-cmd" => {
-                docker_cmd = arguments
-                    .next()
-                    .context("the --docker-cmd flag requires an argument")
-                    .map_err(Error::ArgumentUsage)?
-                    .as_str()
-            }
-            "--jupyter" => flagged_variants |= TagVariants::JUPYTER,
-            "--name" => {
-                name = Some(
-                    arguments
-                        .next()
-                        .context("the --name flag requires a name as an argument")
-                        .map_err(Error::ArgumentUsage)?
-                        .as_str(),
-                );
-            }
-            "-p" | "--port" => {
-                ports.push(
-                    arguments
-                        .next()
-                        .context("the --port flag requires an argument")
-                        .map_err(Error::ArgumentUsage)?
-                        .as_str(),
-                );
-            }
-            "--python3" => flagged_variants |= TagVariants::PY3,
-            "--root" => as_root = true,
-            argument => {
-                if argument.starts_with('-') {
-                    eprintln!("unknown argument to tensorman: {}", argument);
-                    help();
-                }
+    }
 
-                subcommand_args.push(argument)
-            }
+    /// The status code that tensorman exits with when this error is returned.
+    ///
+    /// Like those of Docker, the codes of tensorman's own errors are ones which containers
+    /// rarely exit with, so that they are not mistaken for the status of a container.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ArgumentUsage(..) => 122,
+            Error::Configure(_) => 123,
+            Error::Docker(_) => 125,
+            Error::Exited(code) => *code,
+            Error::Killed(signal) => 128 + signal,
         }
     }
+}
 
-    subcommand_args.extend(arguments.map(|x| x.as_str()));
-    let mut subcommand_args = subcommand_args.into_iter();
+fn signal_description(signal: i32) -> String {
+    match signal::name(signal) {
+        Some(name) => format!("{} ({})", signal, name),
+        None => signal.to_string(),
+    }
+}
 
-    if !flagged_variants.is_empty() {
-        variants = flagged_variants;
+fn main_() -> Result<(), Error> {
+    let arguments: Vec<String> = args().skip(1).collect();
+    let Cli { tag: tag_override, container: container_override, command, flags } =
+        Cli::parse(&arguments)?;
+
+    // Help and the version are printed even when the log level in the environment is invalid.
+    match command {
+        Command::Help(subcommand) => {
+            println!("{}", cli::help(subcommand));
+            return Ok(());
+        }
+        Command::Version => {
+            println!("tensorman {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        _ => (),
     }
 
-    let mut image = Image {
-        variants,
-        source: match specific_container {
-            Some(container) => ImageSource::Container(container),
-            None => ImageSource::Tensorflow(tag),
-        },
+    let level = match flags.verbosity {
+        Some(level) => level,
+        None => log::Level::from_env().map_err(Error::Configure)?,
     };
 
-    let mut runtime = Runtime::new(docker_cmd).map_err(Error::Docker)?;
+    log::set(level);
+
+    // As with docker, a variable without a value is passed through from the host if it is set.
+    let env: BTreeMap<String, String> = flags
+        .env
+        .iter()
+        .filter_map(|&(key, value)| {
+            let value = value.map(String::from).or_else(|| env::var(key).ok())?;
+            Some((key.to_owned(), value))
+        })
+        .collect();
+
+    let command_line = RawConfig {
+        image: container_override.map(String::from),
+        tag: tag_override.map(String::from),
+        variants: Some(flags.variants).filter(|variants| !variants.is_empty()),
+        tty: flags.tty,
+        project_root: flags.project_root,
+        mount_mode: flags.mount_mode,
+        env: Some(env).filter(|env| !env.is_empty()),
+        ..RawConfig::default()
+    };
 
-    match subcommand {
-        "default" => {
-            let tag = subcommand_args
-                .next()
-                .context("a tag must be provided for the default subcommand")
-                .map_err(Error::ArgumentUsage)?;
+    // The config subcommand must work even when the configuration is invalid.
+    if let Command::Config(action) = command {
+        return config::run(action, flags.scope, command_line).map_err(Error::Configure);
+    }
 
-            let source = if tag.starts_with('=') {
-                ImageSourceBuf::Container(tag[1..].into())
-            } else {
-                ImageSourceBuf::Tensorflow(tag.into())
+    let config = Config::read(command_line).map_err(Error::Configure)?;
+
+    // Projects are remembered so that `show IMAGE` can list the projects that pin an image.
+    // This is a convenience, so failing to record one is not treated as an error. A dry run
+    // writes nothing, so it records nothing.
+    let files = config.project_file.iter().chain(config.toolchain_file.iter());
+    for path in files.filter(|_| !flags.dry_run) {
+        let _ = projects::record(path);
+    }
+
+    let mut image = config.image.as_image();
+
+    let engine = engine::new(config.engine, flags.docker_cmd, config.namespace.as_deref());
+    let mut runtime = Runtime::new(&*engine, flags.dry_run).map_err(Error::Docker)?;
+
+    match command {
+        Command::Datasets => config.list_datasets(),
+        Command::Default { tag, variants } => {
+            let source = match tag.strip_prefix('=') {
+                Some(container) => ImageSourceBuf::Container(container.into()),
+                None => ImageSourceBuf::Tensorflow(tag.into()),
             };
 
-            let variants = subcommand_args.collect::<TagVariants>();
+            let variants = variants
+                .into_iter()
+                .map(str::parse::<TagVariants>)
+                .collect::<Result<TagVariants, _>>()
+                .map_err(|why| Error::ArgumentUsage(Some("default"), why))?
+                | flags.variants;
 
-            let new_config =
-                Config { docker_flags: None, image: Some(ImageBuf { variants, source }) };
+            config::write_image(&ImageBuf { variants, source, digest: None }, flags.dry_run)
+                .map_err(Error::Configure)?;
+        }
+        Command::FixPerms { path } => {
+            let path = match path {
+                Some(path) => PathBuf::from(path),
+                None => config.workspace().map_err(Error::Configure)?.host,
+            };
 
-            new_config.write().map_err(Error::Configure)?;
+            runtime
+                .fix_ownership(&image, &path)
+                .with_context(|| format!("failed to fix the owner of files in {}", path.display()))
+                .map_err(Error::Docker)?;
         }
-        "list" => {
+        Command::Home(action) => {
+            if let HomeMode::Workspace = config.home {
+                return Err(Error::Configure(anyhow!(
+                    "the project is the home directory, because home is set to \"workspace\" ({})",
+                    config.origin("home")
+                )));
+            }
+
+            match action {
+                HomeAction::Path => {
+                    println!("{}", home::path(&image).map_err(Error::Configure)?.display())
+                }
+                HomeAction::Clean => home::clean(&image).map_err(Error::Configure)?,
+            }
+        }
+        Command::List => {
             runtime.list().map_err(Error::Docker)?;
         }
-        "pull" => {
-            if let Some(tag) = subcommand_args.next() {
+        Command::Pull { tag } => {
+            if let Some(tag) = tag {
                 image.source = ImageSource::Tensorflow(tag);
-                image.variants = flagged_variants;
+                image.variants = flags.variants;
+                image.digest = None;
             }
 
-            image.pull(docker_cmd).context("failed to pull image").map_err(Error::Docker)?;
+            image
+                .pull(&*engine, flags.dry_run)
+                .context("failed to pull image")
+                .map_err(Error::Docker)?;
         }
-        "remove" => {
-            if subcommand_args.len() == 0 {
-                return Err(Error::ArgumentUsage(anyhow!(
-                    "an image must be provided for the remove subcommand"
-                )));
-            }
-
-            for image in subcommand_args {
+        Command::Remove { images } => {
+            for image in images {
                 runtime
-                    .remove(image, force)
+                    .remove(image, flags.force)
                     .with_context(|| format!("failed to remove container '{}'", image))
                     .map_err(Error::Docker)?;
             }
         }
-        "run" => {
-            let cmd = subcommand_args
-                .next()
-                .context("run subcommand requires a command argument")
-                .map_err(Error::ArgumentUsage)?;
+        Command::Run { cmd, args } => {
+            let workspace = config.workspace().map_err(Error::Configure)?;
 
-            let args: Vec<&str> = subcommand_args.collect();
-            let args: Option<&[&str]> = if args.is_empty() { None } else { Some(&args) };
-            let dflags = config.docker_flags.as_ref().map(Vec::as_slice);
+            // A dry run only prints commands, so nothing is created on the host.
+            let home = match config.home {
+                HomeMode::Managed if flags.dry_run => {
+                    Some(home::path(&image).map_err(Error::Configure)?)
+                }
+                HomeMode::Managed => Some(home::create(&image).map_err(Error::Configure)?),
+                HomeMode::Workspace => None,
+            };
 
-            runtime
-                .run(&image, cmd, name, ports, as_root, args, dflags)
-                .context("failed to run container")
-                .map_err(Error::Docker)?;
-        }
-        "save" => {
-            let container = subcommand_args
-                .next()
-                .context("save subcommand requires a container name as a source")
-                .map_err(Error::ArgumentUsage)?;
+            let volumes = config.volumes().map_err(Error::Configure)?;
+            for (target, volume) in &volumes {
+                volume.prepare(target, flags.dry_run).map_err(Error::Configure)?;
+            }
+
+            let environment = config.environment();
+
+            let spec = RunSpec {
+                image: &image,
+                cmd,
+                args: &args,
+                name: flags.name,
+                ports: config.ports.iter().map(String::as_str).chain(flags.ports).collect(),
+                as_root: flags.as_root,
+                tty: config.tty,
+                env: &environment,
+                volumes: &volumes,
+                secrets: &config.secrets,
+                docker_flags: &config.docker_flags,
+                workspace: &workspace,
+                home: home.as_deref(),
+            };
 
-            let image = subcommand_args
-                .next()
-                .context("save subcommand requires an image name as the destination")
-                .map_err(Error::ArgumentUsage)?;
+            let status =
+                runtime.run(&spec).context("failed to run container").map_err(Error::Docker)?;
+
+            // Files created as root are returned to the user, even if the command failed.
+            let fixed = if flags.as_root && config.fix_ownership_after_root {
+                iter::once(workspace.host.as_path())
+                    .chain(home.as_deref())
+                    .try_for_each(|path| runtime.fix_ownership(&image, path))
+                    .context("failed to return files created as root to the user")
+                    .map_err(Error::Docker)
+            } else {
+                Ok(())
+            };
 
+            Error::from_status(status)?;
+            fixed?;
+        }
+        Command::Save { container, image } => {
             runtime
                 .save(container, image)
                 .with_context(|| format!("failed to save container '{}' as '{}'", container, image))
                 .map_err(Error::Docker)?;
         }
-        "show" => {
-            if subcommand_args.len() == 0 {
-                println!("{}", image);
-            } else {
-                unimplemented!()
+        Command::Show { image: None } => {
+            let source = match image.source {
+                ImageSource::Container(container) => ["=", container].concat(),
+                ImageSource::Tensorflow(tag) => tag.to_owned(),
+            };
+
+            let variants = <Vec<String>>::from(image.variants);
+            let variants = if variants.is_empty() { "none".into() } else { variants.join(" ") };
+
+            println!("{}", image);
+            println!("    image:    {} ({})", source, config.origin("image"));
+            println!("    variants: {} ({})", variants, config.origin("variants"));
+
+            if let Some(digest) = image.digest {
+                println!("    digest:   {} ({})", digest, config.origin("image"));
             }
         }
-        _ => help(),
+        Command::Show { image: Some(argument) } => {
+            runtime.show(argument).map_err(Error::Docker)?;
+        }
+        Command::Config(_) | Command::Help(_) | Command::Version => unreachable!(),
     }
 
     Ok(())
 }
 
-const HELP: &str = "tensorman
-    Tensorflow Docker image manager
-
-USAGE:
-    tensorman [+TAG | =CONTAINER] SUBCOMMAND [FLAGS...]
-
-SUBCOMMANDS:
-    default TAG [VARIANTS...]
-        Defines the default tensorflow image to use when not specified
-
-    list
-        List tensorflow images installed on the system
-
-    pull [TAG]
-        Fetches and updates tensorflow images
-
-    remove ID
-        Removes an image either by its sha sum, or tag
-
-    run COMMAND [-- ARGS...]
-        Mounts an image and executes the given command.
-
-        Use a shell as the COMMAND interactive sessions.
-
-    save CONTAINER NAME
-        Saves an active container with the name of CONTAINER to a new image
-        which shall be named NAME.
-
-    show
-        Show the active image that will be run
-
-FLAGS:
-    -f, --force
-        Apply the subcommand by force (ie: force removal)
-
-    --gpu
-        Uses an image which supports GPU compute
-    
-    --docker-cmd COMMAND
-        Call COMMAND when invoking docker
-
-    --jupyter
-        Uses an image which has Jupyter preinstalled
-
-    --name NAME
-        Gives NAME to the container when it is launched
-
-    -p, --port
-        Specifies a port mapping for the container and host
-
-    --python3
-        Uses an image which supports Python3
-
-    --root
-        Run the docker container as root
-
-    -h, --help
-        Display this information";
-
-fn help() -> ! {
-    println!("{}", HELP);
-    exit(0);
-}
-
 fn main() {
     if let Err(why) = main_() {
-        if let Error::ArgumentUsage(_) = why {
-            println!("{}\n\n", HELP)
+        // The container's own output already explains a failure, as with a shell.
+        if let Error::Exited(_) = why {
+            exit(why.exit_code());
         }
 
         eprintln!("tensorman: {}", why);
@@ -309,6 +323,44 @@ fn main() {
             source = why.source();
         }
 
-        exit(1);
+        if let Error::ArgumentUsage(subcommand, _) = why {
+            eprintln!("\nUSAGE:\n    {}\n", cli::usage_line(subcommand));
+            match subcommand {
+                Some(subcommand) => {
+                    eprintln!("For more information, try `tensorman help {}`", subcommand)
+                }
+                None => eprintln!("For more information, try `tensorman --help`"),
+            }
+        }
+
+        exit(why.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A wait status holds the exit code of a process in its second byte, or else the signal
+    // which killed it in its first.
+    fn exited(code: i32) -> Result<(), Error> {
+        Error::from_status(ExitStatus::from_raw(code << 8))
+    }
+
+    fn killed(signal: i32) -> Result<(), Error> { Error::from_status(ExitStatus::from_raw(signal)) }
+
+    #[test]
+    fn exit_statuses() {
+        assert!(matches!(exited(0), Ok(())));
+        assert!(matches!(exited(1), Err(Error::Exited(1))));
+        assert!(matches!(exited(128), Err(Error::Exited(128))));
+        assert!(matches!(exited(255), Err(Error::Exited(255))));
+    }
+
+    #[test]
+    fn signal_statuses() {
+        assert!(matches!(exited(130), Err(Error::Killed(2))));
+        assert!(matches!(exited(137), Err(Error::Killed(9))));
+        assert!(matches!(killed(15), Err(Error::Killed(15))));
     }
 }
@@ -1,13 +1,22 @@
-use std::path::{Path, PathBuf};
+use anyhow::Context;
+use std::{
+    borrow::Cow,
+    fs::DirBuilder,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    process,
+};
+use xdg::BaseDirectories;
 
 /// Walks up the directory tree to find a file
-pub fn walk_parent_directories(origin: &Path, file: &str) -> Option<PathBuf> {
+///
+/// The nearest directory containing any of the `files` wins, and within a directory, files
+/// are preferred in the order they are given.
+pub fn walk_parent_directories(origin: &Path, files: &[&str]) -> Option<PathBuf> {
     let mut next = Some(origin);
 
     while let Some(parent) = next {
-        let config = parent.join(file);
-
-        if config.exists() {
+        if let Some(config) = files.iter().map(|file| parent.join(file)).find(|p| p.exists()) {
             return Some(config);
         }
 
@@ -16,3 +25,112 @@ pub fn walk_parent_directories(origin: &Path, file: &str) -> Option<PathBuf> {
 
     None
 }
+
+/// Expands a leading `~` to the home directory, and resolves a relative path against `base`.
+pub fn expand_path(path: &Path, base: Option<&Path>) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+
+    match base {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Creates a directory which only the user may access, named after `prefix` and the process,
+/// within the user's runtime directory, or else the temporary directory.
+///
+/// The directory must be new, so that one created by another user is never used.
+pub fn private_directory(prefix: &str) -> anyhow::Result<PathBuf> {
+    let base = BaseDirectories::with_prefix("tensorman")
+        .ok()
+        .and_then(|dirs| dirs.get_runtime_directory().ok().cloned())
+        .unwrap_or_else(std::env::temp_dir);
+
+    let directory = base.join(format!("{}-{}", prefix, process::id()));
+    DirBuilder::new().mode(0o700).create(&directory).with_context(|| {
+        format!("failed to create a private directory at {}", directory.display())
+    })?;
+    Ok(directory)
+}
+
+/// Quotes a word for a POSIX shell, if it contains any characters that the shell would
+/// interpret.
+pub fn shell_quote(word: &str) -> Cow<str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c);
+
+    if !word.is_empty() && word.chars().all(safe) {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+    }
+}
+
+/// Converts a byte offset within `data` into a one-based line and column.
+pub fn line_column(data: &str, offset: usize) -> (usize, usize) {
+    let before = &data[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Finds the candidate closest to `needle`, if any is close enough to be a likely typo.
+pub fn suggest<'a, I>(needle: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let threshold = (needle.chars().count() / 3).max(2);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(needle, candidate), candidate))
+        .filter(|&(distance, _)| distance <= threshold)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_leaves_safe_words() {
+        assert_eq!(shell_quote("tensorflow/tensorflow:2.3-gpu"), "tensorflow/tensorflow:2.3-gpu");
+        assert_eq!(shell_quote("HOME=/home/user@host,x%+_"), "HOME=/home/user@host,x%+_");
+    }
+
+    #[test]
+    fn shell_quote_quotes_special_words() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("{}"), "'{}'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("'"), r"''\'''");
+    }
+}
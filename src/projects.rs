@@ -0,0 +1,71 @@
+use crate::{config, image::Image, toolchain};
+use anyhow::Context;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use xdg::BaseDirectories;
+
+/// Remembers a project file which pins an image, so that `show IMAGE` can find it later.
+pub fn record(file: &Path) -> anyhow::Result<()> {
+    let registry = registry_path()?;
+    let mut projects = read(&registry)?;
+
+    if projects.iter().any(|project| project == file) {
+        return Ok(());
+    }
+
+    projects.push(file.to_owned());
+
+    let data =
+        projects.iter().map(|project| format!("{}\n", project.display())).collect::<String>();
+    let parent = registry.parent().expect("registry path without parent directory");
+    fs::create_dir_all(parent)
+        .and_then(|_| fs::write(&registry, data))
+        .with_context(|| format!("failed to write the project registry at {}", registry.display()))
+}
+
+/// Project files which currently pin the image with the given reference.
+///
+/// Files which have been removed, or which no longer pin the image, are skipped.
+pub fn pinning(reference: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let projects = read(&registry_path()?)?;
+
+    Ok(projects
+        .into_iter()
+        .filter(|project| {
+            let image = match project.file_name().and_then(|name| name.to_str()) {
+                Some("tensorflow-toolchain" | "tensorflow-toolchain.toml") => {
+                    toolchain::read(project).ok()
+                }
+                Some("Tensorman.toml") => config::read_image(project).ok().flatten(),
+                _ => None,
+            };
+
+            // Images are matched by their tag, whether or not a digest pins its content.
+            image.map_or(false, |image| {
+                String::from(&Image { digest: None, ..image.as_image() }) == reference
+            })
+        })
+        .collect())
+}
+
+fn read(registry: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !registry.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = fs::read_to_string(registry).with_context(|| {
+        format!("failed to read the project registry at {}", registry.display())
+    })?;
+
+    Ok(data.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+}
+
+/// The registry of projects, which may not exist yet.
+fn registry_path() -> anyhow::Result<PathBuf> {
+    Ok(BaseDirectories::with_prefix("tensorman")
+        .context("failed to find the XDG base directory for tensorman")?
+        .get_data_home()
+        .join("projects"))
+}
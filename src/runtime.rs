@@ -1,21 +1,27 @@
 use anyhow::Context;
 
 use crate::{
+    engine::{ContainerEngine, Invocation, Mount},
+    home,
     image::{Image, TagVariants},
     info::{iterate_image_info, Info},
+    projects,
+    secret::{self, Secret, Staging},
+    signal,
+    user::ContainerUser,
 };
-use nix::unistd::geteuid;
 use serde::Deserialize;
-use serde_json;
-use std::{env, io, process::Command};
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, IsTerminal},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    time::Instant,
+};
 use tabular::{Row, Table};
 
-#[derive(Deserialize)]
-#[allow(non_snake_case)]
-pub struct DockerContainer {
-    Names: String,
-}
-
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 pub struct DockerImage {
@@ -26,40 +32,222 @@ pub struct DockerImage {
     pub Size:       String,
 }
 
-pub struct Runtime<'a> {
-    docker_cmd: &'a str,
+/// Controls whether a terminal is allocated for the container.
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TtyMode {
+    /// Allocate a terminal only if both stdin and stdout are terminals.
+    #[default]
+    Auto,
+    /// Always allocate a terminal and keep stdin open (`-it`).
+    Always,
+    /// Never allocate a terminal, but keep piped input connected (`-i`).
+    Never,
 }
 
-impl<'a> Runtime<'a> {
-    /// Creates a new runtime for interacting with Docker.
-    pub fn new(docker_cmd: &'a str) -> anyhow::Result<Self> { Ok(Self { docker_cmd }) }
+impl TtyMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TtyMode::Auto => "auto",
+            TtyMode::Always => "always",
+            TtyMode::Never => "never",
+        }
+    }
+
+    /// The interactivity flag to pass to `docker run`, if any.
+    pub fn docker_flag(self) -> Option<&'static str> {
+        let stdin = io::stdin().is_terminal();
+
+        match self {
+            TtyMode::Always => Some("-it"),
+            TtyMode::Never if stdin => None,
+            TtyMode::Never => Some("-i"),
+            TtyMode::Auto if stdin && io::stdout().is_terminal() => Some("-it"),
+            TtyMode::Auto => Some("-i"),
+        }
+    }
+}
 
-    /// Fetches a list of docker containers.
-    pub fn containers(&mut self) -> anyhow::Result<Vec<DockerContainer>> {
-        let context = "failed to fetch list of containers from Docker service";
+/// Selects which directory is mounted into the container.
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectRoot {
+    /// The directory containing the project's `Tensorman.toml` or toolchain file.
+    #[default]
+    Project,
+    /// The current working directory, even if it is inside of a project.
+    Here,
+}
 
-        let json = self
-            .call_docker_output(&["container", "ls", "--format", "{{json .}}"])
-            .context(context)?;
+impl ProjectRoot {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProjectRoot::Project => "project",
+            ProjectRoot::Here => "here",
+        }
+    }
+}
+
+/// Selects where the project is mounted within the container.
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MountMode {
+    /// Mount the project at `/project`.
+    #[default]
+    Project,
+    /// Mount the project at the same absolute path that it has on the host.
+    HostPath,
+}
 
-        Ok(serde_json::Deserializer::from_slice(&json)
-            .into_iter::<DockerContainer>()
-            .collect::<Result<_, _>>()
-            .context(context)?)
+impl MountMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MountMode::Project => "project",
+            MountMode::HostPath => "host-path",
+        }
     }
+}
 
-    /// Fetches a list of docker images.
-    pub fn images(&mut self) -> anyhow::Result<Vec<DockerImage>> {
-        let context = "failed to fetch list of images from Docker service";
+/// The host directory which is mounted into the container, and where commands run within it.
+#[derive(Debug)]
+pub struct Workspace {
+    /// The directory on the host which is mounted.
+    pub host:    PathBuf,
+    /// Where the host directory is mounted within the container.
+    pub target:  PathBuf,
+    /// The working directory within the container.
+    pub workdir: PathBuf,
+}
 
-        let json =
-            self.call_docker_output(&["images", "--format", "{{json .}}"]).context(context)?;
+impl Workspace {
+    /// Mounts `root`, or the current directory if there is no root, as given by `mode`.
+    ///
+    /// The working directory is the subdirectory of the mount which matches the current
+    /// directory, so that relative paths given to the command resolve the same way.
+    pub fn new(root: Option<&Path>, mode: MountMode) -> anyhow::Result<Self> {
+        let pwd = env::current_dir().context("unable to get the current working directory")?;
+        let host = root.map_or_else(|| pwd.clone(), Path::to_path_buf);
+        let target = match mode {
+            MountMode::Project => PathBuf::from("/project"),
+            MountMode::HostPath => host.clone(),
+        };
 
-        Ok(serde_json::Deserializer::from_slice(&json)
-            .into_iter::<DockerImage>()
-            .collect::<Result<_, _>>()
-            .context(context)?)
+        let workdir = match pwd.strip_prefix(&host) {
+            Ok(relative) if !relative.as_os_str().is_empty() => target.join(relative),
+            _ => target.clone(),
+        };
+
+        Ok(Self { host, target, workdir })
     }
+}
+
+/// A host directory which is mounted into the container, as configured in `[volumes]`.
+///
+/// Volumes are keyed by the path that they are mounted at within the container.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Volume {
+    /// The path on the host, which may begin with `~`, or be relative to its config file.
+    pub host:              PathBuf,
+    #[serde(default)]
+    pub read_only:         bool,
+    /// Create the host directory if it does not exist, instead of failing.
+    #[serde(default)]
+    pub create_if_missing: bool,
+}
+
+impl Volume {
+    /// Checks that the volume may be mounted at `target`, creating its host directory if
+    /// it is missing and that is allowed. On a dry run, the directory is not created.
+    pub fn prepare(&self, target: &str, dry_run: bool) -> anyhow::Result<()> {
+        ensure!(
+            target.starts_with('/'),
+            "the volume at '{}' must be mounted at an absolute path",
+            target
+        );
+
+        if self.host.exists() {
+            return Ok(());
+        }
+
+        ensure!(
+            self.create_if_missing,
+            "the host path {} of the volume at '{}' does not exist",
+            self.host.display(),
+            target
+        );
+
+        if dry_run {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(&self.host)
+            .with_context(|| format!("failed to create the volume at {}", self.host.display()))
+    }
+}
+
+/// Everything needed to run a command in a new container.
+pub struct RunSpec<'a> {
+    pub image:        &'a Image<'a>,
+    pub cmd:          &'a str,
+    pub args:         &'a [&'a str],
+    /// Gives the container a name, which is prefixed with `tensorman-`.
+    pub name:         Option<&'a str>,
+    pub ports:        Vec<&'a str>,
+    pub as_root:      bool,
+    pub tty:          TtyMode,
+    pub env:          &'a BTreeMap<String, String>,
+    pub volumes:      &'a BTreeMap<String, Volume>,
+    pub secrets:      &'a BTreeMap<String, Secret>,
+    pub docker_flags: &'a [String],
+    pub workspace:    &'a Workspace,
+    /// The managed home directory, or `None` if the workspace is used as the home directory.
+    pub home:         Option<&'a Path>,
+}
+
+/// Prints a command which is about to be run, and returns whether it should be run.
+///
+/// On a dry run, the command is printed to stdout, as it is the output that was asked for,
+/// and must not be run. Otherwise, it is echoed to stderr when the verbosity is raised.
+pub fn announce(command: &Command, secrets: &[String], dry_run: bool) -> bool {
+    if dry_run {
+        println!("{}", secret::redact(command, secrets));
+    } else {
+        verbose!("{}", secret::redact(command, secrets));
+    }
+
+    !dry_run
+}
+
+/// Runs a docker command, logging how long it took when debugging.
+pub fn timed<T, F: FnOnce(&mut Command) -> T>(command: &mut Command, run: F) -> T {
+    let start = Instant::now();
+    let result = run(command);
+
+    debug!(
+        "{} {} finished in {:.2?}",
+        command.get_program().to_string_lossy(),
+        command.get_args().next().unwrap_or_default().to_string_lossy(),
+        start.elapsed()
+    );
+
+    result
+}
+
+pub struct Runtime<'a> {
+    engine:  &'a dyn ContainerEngine,
+    /// Print the commands which change the system, rather than running them.
+    dry_run: bool,
+}
+
+impl<'a> Runtime<'a> {
+    /// Creates a new runtime for interacting with a container engine.
+    pub fn new(engine: &'a dyn ContainerEngine, dry_run: bool) -> anyhow::Result<Self> {
+        Ok(Self { engine, dry_run })
+    }
+
+    /// Fetches a list of installed images.
+    pub fn images(&mut self) -> anyhow::Result<Vec<DockerImage>> { self.engine.images() }
 
     /// Displays docker images currently installed which are relevant to tensorman.
     pub fn list(&mut self) -> anyhow::Result<()> {
@@ -91,95 +279,217 @@ impl<'a> Runtime<'a> {
 
     /// Removes a Docker image from the system.
     pub fn remove(&mut self, argument: &str, force: bool) -> anyhow::Result<()> {
-        let images = self.images()?;
-        let mut found = false;
-        for info in iterate_image_info(images) {
-            if info.field_matches(argument) {
-                found = true;
-                self.docker_remove_image(&info, force)
-                    .context("failed to remove the docker image")?;
-            }
-        }
+        let images = self.find(argument)?;
 
-        if !found {
+        if images.is_empty() {
             return Err(anyhow!("image not found"));
         }
 
+        for info in images {
+            self.docker_remove_image(&info, force).context("failed to remove the docker image")?;
+        }
+
         Ok(())
     }
 
-    /// Runs a new container from a specified image and configurable parameters.
-    pub fn run(
-        &mut self,
-        image: &Image,
-        cmd: &str,
-        name: Option<&str>,
-        ports: Vec<&str>,
-        as_root: bool,
-        args: Option<&[&str]>,
-        docker_flags: Option<&[String]>,
-    ) -> anyhow::Result<()> {
-        let pwd = env::current_dir().context("unable to get the current working directory")?;
-
-        let mut command = Command::new(self.docker_cmd);
+    /// Displays details about an image, and the projects which pin it.
+    pub fn show(&mut self, argument: &str) -> anyhow::Result<()> {
+        let images = self.find(argument)?;
 
-        let user_: String;
-        let user: &str = if as_root {
-            "root"
+        let references = if images.is_empty() {
+            println!("{}\n    installed: no", reference(argument));
+            vec![reference(argument)]
         } else {
-            user_ = format!("{0}:{0}", geteuid());
-            &*user_
+            for info in &images {
+                println!(
+                    "{}\n    id:        {}\n    size:      {}\n    created:   {}\n    installed: \
+                     yes",
+                    info.reference(),
+                    info.image_id,
+                    info.size,
+                    info.created
+                );
+            }
+
+            images.iter().map(Info::reference).collect()
         };
 
-        command.args(&["run", "-u", &user]);
+        let mut pins = Vec::new();
+        for reference in &references {
+            pins.extend(projects::pinning(reference)?);
+        }
+
+        let mut pins = pins.iter();
+        match pins.next() {
+            Some(pin) => {
+                println!("    pinned by: {}", pin.display());
+                for pin in pins {
+                    println!("               {}", pin.display());
+                }
+            }
+            None => println!("    pinned by: none"),
+        }
+
+        Ok(())
+    }
+
+    /// Finds installed images which match either a tag, an image ID, or a reference.
+    fn find(&mut self, argument: &str) -> anyhow::Result<Vec<Info>> {
+        let reference = reference(argument);
+
+        Ok(iterate_image_info(self.images()?)
+            .filter(|info| info.field_matches(argument) || info.reference() == reference)
+            .collect())
+    }
 
-        if let Some(name) = name {
-            let name: &str = &*["tensorman-", name].concat();
+    /// Runs a new container from a specified image and configurable parameters.
+    ///
+    /// Signals received while the container is running are forwarded to it, and the exit
+    /// status of the container is returned to the caller.
+    pub fn run(&mut self, spec: &RunSpec) -> anyhow::Result<ExitStatus> {
+        let home_target = match spec.home {
+            Some(_) => PathBuf::from(home::TARGET),
+            None => spec.workspace.target.clone(),
+        };
+
+        let name = spec.name.map(|name| ["tensorman-", name].concat());
+        if let Some(name) = &name {
             ensure!(
                 !self.container_exists(name)?,
                 "an active container named {} already exists",
                 name
             );
-            command.arg("--name").arg(name);
         }
 
-        for port in ports {
-            command.arg("-p").arg(port);
+        let env: Vec<(&str, &str)> =
+            spec.env.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+
+        let mut mounts: Vec<Mount> = spec
+            .volumes
+            .iter()
+            .map(|(target, volume)| Mount {
+                host:      volume.host.clone(),
+                target:    PathBuf::from(target),
+                read_only: volume.read_only,
+            })
+            .collect();
+
+        // Secrets written to files for this run are removed once the container exits.
+        let mut staging = Staging::default();
+        let mut secrets = Vec::new();
+        let mut secret_env = Vec::new();
+
+        for (name, secret) in spec.secrets {
+            secrets.push(secret.read(name)?);
         }
 
-        if image.variants.contains(TagVariants::GPU) {
-            command.arg("--gpus=all");
+        for ((name, secret), value) in spec.secrets.iter().zip(&secrets) {
+            let host = match (&secret.env, &secret.file) {
+                (Some(variable), _) => {
+                    secret_env.push((variable.as_str(), value.as_str()));
+                    continue;
+                }
+                (None, Some(file)) => file.clone(),
+                // A dry run writes nothing, so the path of a staged secret is only a placeholder.
+                (None, None) if self.dry_run => PathBuf::from(format!("<secret {}>", name)),
+                (None, None) => staging.write(name, value)?,
+            };
+
+            let target = Path::new(secret::TARGET).join(name);
+            mounts.push(Mount { host, target, read_only: true });
         }
 
-        command.arg("-e").arg("HOME=/project");
+        let reference = String::from(spec.image);
+        let invocation = Invocation {
+            image: reference.clone(),
+            cmd: spec.cmd,
+            args: spec.args,
+            name: name.as_deref(),
+            ports: &spec.ports,
+            gpu: spec.image.variants.contains(TagVariants::GPU),
+            as_root: spec.as_root,
+            tty: spec.tty,
+            home: spec.home,
+            home_target: &home_target,
+            env,
+            secret_env,
+            mounts,
+            docker_flags: spec.docker_flags,
+            workspace: spec.workspace,
+            dry_run: self.dry_run,
+        };
 
-        if let Some(args) = docker_flags {
-            command.args(args);
+        let mut command = self.engine.run_command(&invocation)?;
+
+        if let Some(mut prepare) = self.engine.prepare_command(&reference, self.dry_run)? {
+            if announce(&prepare, &[], self.dry_run) {
+                let status =
+                    timed(&mut prepare, Command::status).context("failed to prepare the image")?;
+                ensure!(
+                    status.success(),
+                    "preparing the image {} failed with {}",
+                    reference,
+                    status
+                );
+            }
+        }
+
+        if !announce(&command, &secrets, self.dry_run) {
+            return Ok(ExitStatus::from_raw(0));
         }
 
-        command.args(&[
-            "-it",
-            "--rm",
-            "-v",
-            &format!("{}:/project", pwd.display()),
-            "-w",
-            "/project",
-            &String::from(image),
-            cmd,
+        timed(&mut command, signal::run_forwarding)
+            .with_context(|| format!("failed to launch the {} run command", self.engine.name()))
+    }
+
+    /// Returns files owned by root under `path` to the invoking user.
+    ///
+    /// Files created by a `--root` run can only be changed by root, so a throwaway container
+    /// of the `image` is run as root to change their owner.
+    pub fn fix_ownership(&mut self, image: &Image, path: &Path) -> anyhow::Result<()> {
+        let path =
+            path.canonicalize().with_context(|| format!("failed to resolve {}", path.display()))?;
+
+        ensure!(path.is_dir(), "{} is not a directory", path.display());
+        ensure!(path.parent().is_some(), "refusing to change the owner of files under /");
+
+        if !self.engine.root_owns_files() {
+            verbose!(
+                "files created by root are already owned by the user with rootless containers"
+            );
+            return Ok(());
+        }
+
+        let user = ContainerUser::current();
+        let mut command = self.engine.command();
+        command.args(["run", "--rm", "-u", "root", "-v"]);
+        command.arg(format!("{}:/fix-perms", path.display()));
+        command.args([
+            &self.engine.qualify(String::from(image)),
+            "find",
+            "/fix-perms",
+            "-user",
+            "0",
+            "-exec",
         ]);
+        command.args(["chown", "-h", &user.spec(), "{}", "+"]);
 
-        if let Some(args) = args {
-            command.args(args);
+        if !announce(&command, &[], self.dry_run) {
+            return Ok(());
         }
 
-        eprintln!("{:?}", command);
-        command.status().context("docker run command exited with a bad status")?;
+        let status = timed(&mut command, Command::status)
+            .context("failed to launch the docker run command")?;
+        ensure!(status.success(), "the container changing file ownership failed with {}", status);
         Ok(())
     }
 
     /// Saves an active container to a new image in the tensorman repository
     pub fn save(&mut self, container: &str, repo: &str) -> anyhow::Result<()> {
-        let container: &str = &*["tensorman-", container].concat();
+        let container: &str = &["tensorman-", container].concat();
+
+        // An engine which cannot save containers fails before the engine is queried.
+        let command = self.engine.commit_command(container, ["tensorman:", repo].concat())?;
 
         ensure!(self.container_exists(container)?, "the container to be saved does not exist");
 
@@ -205,7 +515,7 @@ impl<'a> Runtime<'a> {
 
         // self.tokio.block_on(future).map_err(|failure| failure.compat())
 
-        self.commit_command(container, repo).context("failed to commit container")?;
+        self.commit(command).context("failed to commit container")?;
 
         Ok(())
     }
@@ -213,33 +523,40 @@ impl<'a> Runtime<'a> {
     /// Queries docker for a list of containers, and returns `Ok(true)` if container
     /// with a compatible name is found.
     fn container_exists(&mut self, name: &str) -> anyhow::Result<bool> {
-        Ok(self.containers()?.iter().any(|c| c.Names.split(", ").any(|e| e == name)))
+        Ok(self.engine.containers()?.iter().any(|container| container == name))
     }
 
-    fn commit_command(&self, container: &str, repo: &str) -> io::Result<()> {
-        let image = ["tensorman:", repo].concat();
-        Command::new(self.docker_cmd).args(&["commit", container, &image]).status().map(|_| ())
+    fn commit(&self, mut command: Command) -> anyhow::Result<()> {
+        if !announce(&command, &[], self.dry_run) {
+            return Ok(());
+        }
+
+        let status = timed(&mut command, Command::status)?;
+        ensure!(status.success(), "the commit command failed with {}", status);
+        Ok(())
     }
 
-    fn docker_remove_image(&self, info: &Info, force: bool) -> io::Result<()> {
-        let mut command = Command::new(self.docker_cmd);
-        command.args(&["rmi", &info.image_id]);
+    fn docker_remove_image(&self, info: &Info, force: bool) -> anyhow::Result<()> {
+        let mut command = self.engine.remove_command(info, force)?;
 
-        if force {
-            command.arg("--force");
+        if !announce(&command, &[], self.dry_run) {
+            return Ok(());
         }
 
-        command.status().map(|_| ())
+        let status = timed(&mut command, Command::status)?;
+        ensure!(status.success(), "removing the image {} failed with {}", info.image_id, status);
+        Ok(())
     }
+}
 
-    fn call_docker_output(&self, args: &[&str]) -> anyhow::Result<Vec<u8>> {
-        let mut command = Command::new(self.docker_cmd);
-        command.args(args);
-        let output = command.output()?;
-        if output.status.success() {
-            Ok(output.stdout)
-        } else {
-            Err(anyhow::Error::msg(String::from_utf8_lossy(&output.stderr).trim().to_string()))
-        }
+/// Expands an image argument into a full reference, such as `tensorflow/tensorflow:latest`.
+///
+/// Custom images may be given as `=NAME`, while any other argument without a repository
+/// is treated as a tag of the tensorflow image.
+fn reference(argument: &str) -> String {
+    match argument.strip_prefix('=') {
+        Some(container) => ["tensorman:", container].concat(),
+        None if argument.contains(':') => argument.to_owned(),
+        None => ["tensorflow/tensorflow:", argument].concat(),
     }
 }
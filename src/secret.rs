@@ -0,0 +1,210 @@
+use crate::{
+    config,
+    misc::{private_directory, shell_quote},
+};
+use anyhow::Context;
+use serde::Deserialize;
+use std::{
+    ffi::OsStr,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    process::Command,
+};
+
+/// Where secrets which are not injected as environment variables are mounted.
+pub const TARGET: &str = "/run/secrets";
+
+/// Parts of environment variable names which mark their values as sensitive.
+const SENSITIVE: &[&str] = &["TOKEN", "KEY", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL"];
+
+/// A secret given to the container, as configured in the `[secrets]` table.
+///
+/// The secret is read from either a `value` or a host `file`, and is mounted as a file at
+/// `/run/secrets/<name>`, unless `env` names a variable to inject it as instead.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Secret {
+    pub value: Option<String>,
+    /// A file on the host holding the secret, which may begin with `~`, or be relative to
+    /// its config file.
+    pub file:  Option<PathBuf>,
+    pub env:   Option<String>,
+}
+
+impl Secret {
+    /// Validates and reads the secret, from its value or the file which holds it.
+    pub fn read(&self, name: &str) -> anyhow::Result<String> {
+        ensure!(
+            !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+            "'{}' is not a valid secret name",
+            name
+        );
+
+        if let Some(variable) = &self.env {
+            ensure!(
+                config::is_variable_name(variable),
+                "the secret '{}' has an invalid variable name '{}'",
+                name,
+                variable
+            );
+        }
+
+        match (&self.value, &self.file) {
+            (Some(value), None) => Ok(value.clone()),
+            (None, Some(file)) => fs::read_to_string(file)
+                .map(|secret| secret.trim_end_matches(&['\r', '\n'][..]).to_owned())
+                .with_context(|| {
+                    format!("failed to read the secret '{}' from {}", name, file.display())
+                }),
+            _ => Err(anyhow!("the secret '{}' must have exactly one of 'value' or 'file'", name)),
+        }
+    }
+}
+
+/// A private directory holding secrets written for a single run, which is removed on drop.
+#[derive(Default)]
+pub struct Staging {
+    directory: Option<PathBuf>,
+}
+
+impl Staging {
+    /// Writes a secret to a file which only the user may read, returning its path.
+    pub fn write(&mut self, name: &str, secret: &str) -> anyhow::Result<PathBuf> {
+        let directory = match &self.directory {
+            Some(directory) => directory.clone(),
+            None => {
+                let directory = private_directory("tensorman-secrets")?;
+
+                self.directory = Some(directory.clone());
+                directory
+            }
+        };
+
+        let path = directory.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(secret.as_bytes()))
+            .with_context(|| format!("failed to write the secret '{}'", name))?;
+
+        Ok(path)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Some(directory) = self.directory.take() {
+            let _ = fs::remove_dir_all(directory);
+        }
+    }
+}
+
+/// Whether the value of an environment variable should be hidden from printed commands.
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SENSITIVE.iter().any(|part| name.contains(part))
+}
+
+/// Formats a command as it would be typed into a shell, with each of the `secrets` and the
+/// values of sensitive environment variables replaced by `***`.
+///
+/// Variables which are set in the environment of the command are shown before it.
+pub fn redact(command: &Command, secrets: &[String]) -> String {
+    let mut display = String::new();
+
+    for (name, value) in command.get_envs() {
+        if let Some(value) = value {
+            let value = if secrets.iter().any(|secret| **secret == *value) {
+                "***".into()
+            } else {
+                value.to_string_lossy()
+            };
+
+            display.push_str(&name.to_string_lossy());
+            display.push('=');
+            display.push_str(&shell_quote(&value));
+            display.push(' ');
+        }
+    }
+
+    display.push_str(&shell_quote(&command.get_program().to_string_lossy()));
+    let mut environment_follows = false;
+
+    for argument in command.get_args().map(OsStr::to_string_lossy) {
+        let mut argument = argument.into_owned();
+
+        for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+            argument = argument.replace(secret.as_str(), "***");
+        }
+
+        let variable = match argument.strip_prefix("--env=") {
+            Some(variable) => Some(("--env=", variable)),
+            None if environment_follows => Some(("", argument.as_str())),
+            None => None,
+        };
+
+        let redacted = variable.and_then(|(flag, variable)| {
+            let (name, _) = variable.split_once('=')?;
+            Some(format!("{}{}=***", flag, name)).filter(|_| is_sensitive(name))
+        });
+
+        if let Some(redacted) = redacted {
+            argument = redacted;
+        }
+
+        environment_follows = argument == "-e" || argument == "--env";
+        display.push(' ');
+        display.push_str(&shell_quote(&argument));
+    }
+
+    display
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docker(arguments: &[&str]) -> Command {
+        let mut command = Command::new("docker");
+        command.args(arguments);
+        command
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let secrets = ["s3cr3t".to_owned(), String::new()];
+        let command = docker(&["run", "-e", "VALUE=s3cr3t", "-v", "/s3cr3t:/x"]);
+        assert_eq!(redact(&command, &secrets), "docker run -e 'VALUE=***' -v '/***:/x'");
+    }
+
+    #[test]
+    fn redacts_sensitive_variables() {
+        let command = docker(&["run", "-e", "API_TOKEN=abc", "--env", "db_password=x y"]);
+        assert_eq!(redact(&command, &[]), "docker run -e 'API_TOKEN=***' --env 'db_password=***'");
+
+        let command = docker(&["run", "--env=AWS_SECRET_ACCESS_KEY=abc", "--env=HOME=/home"]);
+        assert_eq!(
+            redact(&command, &[]),
+            "docker run '--env=AWS_SECRET_ACCESS_KEY=***' --env=HOME=/home"
+        );
+    }
+
+    #[test]
+    fn keeps_other_arguments() {
+        let command = docker(&["run", "-e", "PATH=/bin", "-e", "API_KEY", "image", "KEY=value"]);
+        assert_eq!(redact(&command, &[]), "docker run -e PATH=/bin -e API_KEY image KEY=value");
+    }
+
+    #[test]
+    fn redacts_environment_of_command() {
+        let secrets = ["s3cr3t".to_owned()];
+        let mut command = docker(&["run", "-e", "WANDB_API_KEY"]);
+        command.env("WANDB_API_KEY", "s3cr3t");
+        assert_eq!(redact(&command, &secrets), "WANDB_API_KEY='***' docker run -e WANDB_API_KEY");
+    }
+}
@@ -0,0 +1,79 @@
+use nix::{
+    libc::{self, c_int, c_void, siginfo_t},
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+};
+use std::{
+    convert::TryFrom,
+    io,
+    process::{Command, ExitStatus},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// Signals which are relayed from tensorman to the running container.
+const FORWARDED: [Signal; 3] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP];
+
+/// The PID of the child process that signals are currently forwarded to.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// A signal received before the child was spawned, which is relayed once it has been.
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(signal: c_int, info: *mut siginfo_t, _context: *mut c_void) {
+    // Signals generated by the terminal are already delivered to every process in the
+    // foreground process group, so only signals sent by another process are relayed.
+    let sent_by_process = unsafe { info.as_ref() }.map_or(true, |info| info.si_code <= 0);
+    let child = CHILD.load(Ordering::SeqCst);
+
+    if sent_by_process {
+        if child > 0 {
+            unsafe {
+                libc::kill(child, signal);
+            }
+        } else {
+            PENDING.store(signal, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Spawns the command and waits for it to exit, forwarding SIGINT, SIGTERM, and SIGHUP
+/// to the child for as long as it is running.
+///
+/// The handlers are installed before the child is spawned, so that a signal cannot kill
+/// tensorman while leaving the container running. The child's handlers are reset by exec.
+pub fn run_forwarding(command: &mut Command) -> io::Result<ExitStatus> {
+    let action =
+        SigAction::new(SigHandler::SigAction(forward), SaFlags::SA_RESTART, SigSet::empty());
+
+    // Forwarding is best-effort: a handler that fails to install leaves the default action.
+    let previous: Vec<_> = FORWARDED
+        .iter()
+        .filter_map(|&signal| unsafe { sigaction(signal, &action) }.ok().map(|old| (signal, old)))
+        .collect();
+
+    let status = command.spawn().and_then(|mut child| {
+        CHILD.store(child.id() as i32, Ordering::SeqCst);
+
+        let pending = PENDING.swap(0, Ordering::SeqCst);
+        if pending > 0 {
+            unsafe {
+                libc::kill(child.id() as i32, pending);
+            }
+        }
+
+        child.wait()
+    });
+
+    CHILD.store(0, Ordering::SeqCst);
+    PENDING.store(0, Ordering::SeqCst);
+
+    for (signal, old) in &previous {
+        let _ = unsafe { sigaction(*signal, old) };
+    }
+
+    status
+}
+
+/// The name of a signal number, such as `SIGKILL`, if it is known.
+pub fn name(signal: i32) -> Option<&'static str> {
+    Signal::try_from(signal).ok().map(|s| s.as_str())
+}
@@ -1,23 +1,237 @@
 use crate::{
+    config::check_keys,
     image::{ImageBuf, ImageSourceBuf, TagVariants},
-    misc::walk_parent_directories,
+    misc::{line_column, walk_parent_directories},
 };
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item};
 
-pub fn toolchain_override() -> Option<ImageBuf> {
-    let current_dir = std::env::current_dir().ok()?;
+/// The structured toolchain file, which is preferred over the legacy format.
+const TOML_FILE: &str = "tensorflow-toolchain.toml";
 
-    let path = walk_parent_directories(&current_dir, "tensorflow-toolchain")?;
-    let info = std::fs::read_to_string(&path).ok()?;
+/// The legacy toolchain file, which holds a tag followed by its variants.
+const LEGACY_FILE: &str = "tensorflow-toolchain";
 
-    let mut iterator = info.trim().split_ascii_whitespace();
-    let tag = iterator.next()?;
+/// Every key which may be set in the `[toolchain]` table of a structured toolchain file.
+const KEYS: &[&str] = &["channel", "digest", "image", "tag", "variants"];
 
-    Some(ImageBuf {
-        variants: iterator.collect::<TagVariants>(),
-        source:   if tag.starts_with('=') {
-            ImageSourceBuf::Container(tag[1..].into())
-        } else {
-            ImageSourceBuf::Tensorflow(tag.into())
+/// An image pinned by a `tensorflow-toolchain` or `tensorflow-toolchain.toml` file.
+pub struct Toolchain {
+    pub path:  PathBuf,
+    pub image: ImageBuf,
+}
+
+/// A structured toolchain file, as it is written in `tensorflow-toolchain.toml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolchainFile {
+    toolchain: RawToolchain,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawToolchain {
+    channel:  Option<Channel>,
+    digest:   Option<String>,
+    image:    Option<String>,
+    tag:      Option<String>,
+    variants: Option<TagVariants>,
+}
+
+/// A tag which is continuously updated to follow a release stream.
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+enum Channel {
+    Latest,
+    Nightly,
+}
+
+impl Channel {
+    fn tag(self) -> &'static str {
+        match self {
+            Channel::Latest => "latest",
+            Channel::Nightly => "nightly",
+        }
+    }
+}
+
+/// Finds the toolchain file of the project in the current directory, if it has one.
+///
+/// A toolchain file which exists but cannot be read or parsed is an error, rather than
+/// silently falling back to the image configured elsewhere.
+pub fn toolchain_override() -> anyhow::Result<Option<Toolchain>> {
+    let current_dir =
+        std::env::current_dir().context("failed to fetch the current working directory")?;
+
+    let path = match walk_parent_directories(&current_dir, &[TOML_FILE, LEGACY_FILE]) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    if path.file_name().map_or(false, |name| name == TOML_FILE) {
+        let legacy = path.with_file_name(LEGACY_FILE);
+        if legacy.exists() {
+            return Err(anyhow!(
+                "both {} and {} exist; remove one of them",
+                path.display(),
+                legacy.display()
+            ));
+        }
+    }
+
+    Ok(Some(Toolchain { image: read(&path)?, path }))
+}
+
+/// Reads the image pinned by the toolchain file at `path`.
+///
+/// Files with a `.toml` extension are read in the structured format.
+pub fn read(path: &Path) -> anyhow::Result<ImageBuf> {
+    let info = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read toolchain file at {}", path.display()))?;
+
+    match path.extension().map_or(false, |extension| extension == "toml") {
+        true => parse_toml(&info)
+            .with_context(|| format!("invalid toolchain file at {}", path.display())),
+        false => parse_legacy(&info, path),
+    }
+}
+
+fn parse_toml(info: &str) -> anyhow::Result<ImageBuf> {
+    let document = ImDocument::parse(info)?;
+    check_keys(info, document.as_table(), &["toolchain"])?;
+
+    match document.get("toolchain") {
+        Some(Item::Table(table)) => check_keys(info, table, KEYS)?,
+        Some(_) => return Err(anyhow!("'toolchain' must be a table")),
+        None => return Err(anyhow!("expected a [toolchain] table")),
+    }
+
+    let RawToolchain { channel, digest, image, tag, variants } =
+        toml::from_str::<ToolchainFile>(info)?.toolchain;
+
+    let source = match (image, tag, channel) {
+        (Some(image), None, None) => ImageSourceBuf::Container(image.into()),
+        (None, Some(tag), None) => ImageSourceBuf::Tensorflow(tag.into()),
+        (None, None, Some(channel)) => ImageSourceBuf::Tensorflow(channel.tag().into()),
+        (None, None, None) => return Err(anyhow!("expected one of 'tag', 'image', or 'channel'")),
+        _ => return Err(anyhow!("only one of 'tag', 'image', or 'channel' may be set")),
+    };
+
+    if let Some(digest) = &digest {
+        if let ImageSourceBuf::Container(_) = source {
+            return Err(anyhow!("'digest' may only pin a 'tag' or 'channel'"));
+        }
+
+        let valid = digest.strip_prefix("sha256:").map_or(false, |hex| {
+            hex.len() == 64 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+        });
+
+        if !valid {
+            return Err(anyhow!(
+                "'{}' is not a digest of the form 'sha256:<64 hex digits>'",
+                digest
+            ));
+        }
+    }
+
+    Ok(ImageBuf {
+        variants: variants.unwrap_or_default(),
+        source,
+        digest: digest.map(String::into_boxed_str),
+    })
+}
+
+fn parse_legacy(info: &str, path: &Path) -> anyhow::Result<ImageBuf> {
+    let mut iterator = info.split_ascii_whitespace();
+    let tag = iterator.next().ok_or_else(|| {
+        anyhow!("invalid toolchain file at {}: expected a tag or =IMAGE", path.display())
+    })?;
+
+    let variants = iterator
+        .map(|variant| {
+            variant.parse::<TagVariants>().with_context(|| {
+                let (line, column) =
+                    line_column(info, variant.as_ptr() as usize - info.as_ptr() as usize);
+                format!("invalid toolchain file at {}:{}:{}", path.display(), line, column)
+            })
+        })
+        .collect::<anyhow::Result<TagVariants>>()?;
+
+    Ok(ImageBuf {
+        variants,
+        source: match tag.strip_prefix('=') {
+            Some(container) => ImageSourceBuf::Container(container.into()),
+            None => ImageSourceBuf::Tensorflow(tag.into()),
         },
+        digest: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(image: anyhow::Result<ImageBuf>) -> String {
+        String::from(&image.unwrap().as_image())
+    }
+
+    fn legacy(info: &str) -> anyhow::Result<ImageBuf> {
+        parse_legacy(info, Path::new("tensorflow-toolchain"))
+    }
+
+    #[test]
+    fn legacy_format() {
+        assert_eq!(reference(legacy("2.3\n")), "tensorflow/tensorflow:2.3");
+        assert_eq!(reference(legacy("2.3 gpu\npython3\n")), "tensorflow/tensorflow:2.3-gpu-py3");
+        assert_eq!(reference(legacy("=custom")), "tensorman:custom");
+    }
+
+    #[test]
+    fn legacy_format_errors() {
+        let error = legacy("  \n").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "invalid toolchain file at tensorflow-toolchain: expected a tag or =IMAGE"
+        );
+
+        let error = legacy("2.3 gpu\n  bogus\n").unwrap_err();
+        assert_eq!(error.to_string(), "invalid toolchain file at tensorflow-toolchain:2:3");
+        assert!(error.root_cause().to_string().starts_with("unknown variant 'bogus'"));
+    }
+
+    #[test]
+    fn toml_format() {
+        let info = "[toolchain]\ntag = \"2.3\"\nvariants = [\"gpu\"]\n";
+        assert_eq!(reference(parse_toml(info)), "tensorflow/tensorflow:2.3-gpu");
+
+        let info = "[toolchain]\nchannel = \"nightly\"\n";
+        assert_eq!(reference(parse_toml(info)), "tensorflow/tensorflow:nightly");
+
+        let info = "[toolchain]\nimage = \"custom\"\n";
+        assert_eq!(reference(parse_toml(info)), "tensorman:custom");
+
+        let digest = ["sha256:", &"0f".repeat(32)].concat();
+        let info = format!("[toolchain]\ntag = \"2.3\"\ndigest = \"{}\"\n", digest);
+        assert_eq!(parse_toml(&info).unwrap().digest.as_deref(), Some(digest.as_str()));
+    }
+
+    #[test]
+    fn toml_format_errors() {
+        let error = |info: &str| parse_toml(info).unwrap_err().to_string();
+
+        assert_eq!(error("\n"), "expected a [toolchain] table");
+        assert_eq!(error("[toolchain]\n"), "expected one of 'tag', 'image', or 'channel'");
+        assert_eq!(
+            error("[toolchain]\ntag = \"2.3\"\nchannel = \"latest\"\n"),
+            "only one of 'tag', 'image', or 'channel' may be set"
+        );
+        assert_eq!(
+            error("[toolchain]\nimage = \"custom\"\ndigest = \"sha256:00\"\n"),
+            "'digest' may only pin a 'tag' or 'channel'"
+        );
+        assert!(parse_toml("[toolchain]\ntag = \"2.3\"\ndigest = \"sha256:00\"\n").is_err());
+        assert!(parse_toml("[toolchain]\ntga = \"2.3\"\n").is_err());
+    }
+}
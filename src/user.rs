@@ -0,0 +1,247 @@
+use anyhow::Context;
+use nix::unistd::{getegid, geteuid, getgroups, Gid, Group, Uid, User};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+use xdg::BaseDirectories;
+
+/// The host user which the container is run as.
+pub struct ContainerUser {
+    pub uid:    Uid,
+    pub gid:    Gid,
+    /// Supplementary groups of the host user, excluding the primary group.
+    pub groups: Vec<Gid>,
+    pub name:   String,
+}
+
+impl ContainerUser {
+    /// The effective user and groups of the tensorman process.
+    pub fn current() -> Self {
+        let (uid, gid) = (geteuid(), getegid());
+
+        let mut groups = getgroups().unwrap_or_default();
+        groups.retain(|&group| group != gid);
+        groups.sort_by_key(|group| group.as_raw());
+        groups.dedup();
+
+        let name = User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map_or_else(|| "tensorman".to_owned(), |user| user.name);
+
+        Self { uid, gid, groups, name }
+    }
+
+    /// The argument given to `docker run -u`.
+    pub fn spec(&self) -> String { format!("{}:{}", self.uid, self.gid) }
+
+    /// Writes the `passwd` and `group` files to mount into the container, so that the user
+    /// has a name and a home directory there. Their paths are returned in that order.
+    ///
+    /// The files are those of the image, as they are returned by `image_files`, with entries
+    /// for the user and its groups. On a dry run, nothing is read or written, and only the
+    /// paths are returned.
+    pub fn write_files<F>(
+        &self,
+        home: &Path,
+        dry_run: bool,
+        image_files: F,
+    ) -> anyhow::Result<(PathBuf, PathBuf)>
+    where
+        F: FnOnce() -> anyhow::Result<(String, String)>,
+    {
+        let directory = BaseDirectories::with_prefix("tensorman")
+            .context("failed to find the XDG base directory for tensorman")?
+            .get_data_home()
+            .join("etc");
+
+        if dry_run {
+            return Ok((directory.join("passwd"), directory.join("group")));
+        }
+
+        let (passwd, group) = image_files()?;
+        let (passwd, group) = self.files(home, &passwd, &group);
+
+        fs::create_dir_all(&directory).with_context(|| {
+            format!("failed to create the directory for user files at {}", directory.display())
+        })?;
+
+        Ok((write(&directory, "passwd", &passwd)?, write(&directory, "group", &group)?))
+    }
+
+    /// The `passwd` and `group` files of an image, with entries added for the user and its
+    /// groups.
+    ///
+    /// Entries of the image which have the name or the ID of an added entry are replaced, so
+    /// that the user's own name and home directory are found for its ID.
+    fn files(&self, home: &Path, passwd: &str, group: &str) -> (String, String) {
+        let user = format!(
+            "{}:x:{}:{}:{}:{}:/bin/bash",
+            self.name,
+            self.uid,
+            self.gid,
+            self.name,
+            home.display()
+        );
+
+        // The root group of the image is kept, even if the user is a member of it.
+        let mut groups = Vec::new();
+        if self.gid.as_raw() != 0 {
+            groups.push(format!("{}:x:{}:", group_name(self.gid), self.gid));
+        }
+
+        for &gid in self.groups.iter().filter(|gid| gid.as_raw() != 0) {
+            groups.push(format!("{}:x:{}:{}", group_name(gid), gid, self.name));
+        }
+
+        (append(passwd, &[user]), append(group, &groups))
+    }
+}
+
+/// The `passwd` and `group` files of the image with the ID `id`, which are read with `read`
+/// only if they are not cached yet, as they never change for an image ID.
+///
+/// If they cannot be read, such as from an image without `cat`, only root is defined.
+pub fn image_files<F>(id: &str, read: F) -> anyhow::Result<(String, String)>
+where
+    F: Fn(&str) -> anyhow::Result<String>,
+{
+    let directory = BaseDirectories::with_prefix("tensorman")
+        .context("failed to find the XDG base directory for tensorman")?
+        .get_cache_home()
+        .join("etc")
+        .join(id.replace(':', "-"));
+
+    let cached = |name| fs::read_to_string(directory.join(name));
+    if let (Ok(passwd), Ok(group)) = (cached("passwd"), cached("group")) {
+        return Ok((passwd, group));
+    }
+
+    let (passwd, group) = match (read("/etc/passwd"), read("/etc/group")) {
+        (Ok(passwd), Ok(group)) => (passwd, group),
+        (Err(why), _) | (_, Err(why)) => {
+            warn!("failed to read the users of the image, so only root is defined: {}", why);
+            return Ok(("root:x:0:0:root:/root:/bin/bash\n".into(), "root:x:0:\n".into()));
+        }
+    };
+
+    fs::create_dir_all(&directory).with_context(|| {
+        format!("failed to create the directory for user files at {}", directory.display())
+    })?;
+
+    write(&directory, "passwd", &passwd)?;
+    write(&directory, "group", &group)?;
+    Ok((passwd, group))
+}
+
+/// The name of a host group, or a generated name if the group has none.
+fn group_name(gid: Gid) -> String {
+    Group::from_gid(gid).ok().flatten().map_or_else(|| format!("group{}", gid), |group| group.name)
+}
+
+/// Appends `entries` to a `passwd` or `group` file, replacing the lines of the file which
+/// have the name or the ID of one of the entries.
+fn append(file: &str, entries: &[String]) -> String {
+    // Both files give the name in the first field, and the ID in the third.
+    fn key(line: &str) -> (Option<&str>, Option<&str>) {
+        let mut fields = line.split(':');
+        (fields.next(), fields.nth(1))
+    }
+
+    let mut data = String::new();
+    for line in file.lines() {
+        let (name, id) = key(line);
+        let replaced = entries
+            .iter()
+            .map(|entry| key(entry))
+            .any(|(entry_name, entry_id)| entry_name == name || (id.is_some() && entry_id == id));
+
+        if !replaced {
+            data.push_str(line);
+            data.push('\n');
+        }
+    }
+
+    for entry in entries {
+        data.push_str(entry);
+        data.push('\n');
+    }
+
+    data
+}
+
+/// Replaces a generated file atomically, so that a container which is already running keeps
+/// the file it was started with.
+fn write(directory: &Path, name: &str, data: &str) -> anyhow::Result<PathBuf> {
+    let path = directory.join(name);
+    let temporary = directory.join(format!("{}.{}.tmp", name, process::id()));
+
+    fs::write(&temporary, data)
+        .and_then(|_| fs::rename(&temporary, &path))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/bash
+daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+ubuntu:x:1999999000:1999999000:Ubuntu:/home/ubuntu:/bin/bash
+";
+
+    const GROUP: &str = "root:x:0:
+daemon:x:1:
+ubuntu:x:1999999000:
+";
+
+    fn user(uid: u32, gid: u32, groups: &[u32], name: &str) -> ContainerUser {
+        ContainerUser {
+            uid:    Uid::from_raw(uid),
+            gid:    Gid::from_raw(gid),
+            groups: groups.iter().copied().map(Gid::from_raw).collect(),
+            name:   name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn files_of_root() {
+        let (passwd, group) = user(0, 0, &[], "root").files(Path::new("/project"), PASSWD, GROUP);
+
+        assert_eq!(
+            passwd,
+            "daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+ubuntu:x:1999999000:1999999000:Ubuntu:/home/ubuntu:/bin/bash
+root:x:0:0:root:/project:/bin/bash
+"
+        );
+        assert_eq!(group, GROUP);
+    }
+
+    #[test]
+    fn files_of_user() {
+        let user = user(1999999000, 1999999001, &[0, 1999999002], "alice");
+        let (passwd, group) = user.files(Path::new("/home/tensorman"), PASSWD, GROUP);
+
+        assert_eq!(
+            passwd,
+            "root:x:0:0:root:/root:/bin/bash
+daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+alice:x:1999999000:1999999001:alice:/home/tensorman:/bin/bash
+"
+        );
+        assert_eq!(
+            group,
+            "root:x:0:
+daemon:x:1:
+ubuntu:x:1999999000:
+group1999999001:x:1999999001:
+group1999999002:x:1999999002:alice
+"
+        );
+    }
+}